@@ -0,0 +1,259 @@
+// Generate the builder style setters of a class.
+use std::collections::HashMap;
+
+use proc_macro2::TokenStream;
+use quote::{quote, format_ident};
+
+use crate::{Class, For, Stmt, Stmts};
+use crate::expr::{BinOp, Expr};
+use crate::model::{self, Field, Model, Type};
+
+pub fn gen_builder(class: &Class, model: &Model) -> TokenStream {
+    let name = &model.name;
+    let version = model.version_field().map(|f| &f.name);
+    let versioned = !model.versions.is_empty();
+
+    let mut out = TokenStream::new();
+    for f in model.struct_fields(0) {
+        let ident = &f.ident;
+        let ty = f.tokens();
+        let setter = format_ident!("with_{}", ident.to_string().trim_end_matches('_'));
+        let (doc, fit) = if versioned && Some(&f.name) != version {
+            (format!(" Set `{}`, builder style. Raises the version if the value needs it.", ident), quote!(self.fit_version();))
+        } else {
+            (format!(" Set `{}`, builder style.", ident), quote!())
+        };
+        out.extend(quote! {
+            #[doc = #doc]
+            pub fn #setter(mut self, #ident: #ty) -> #name {
+                self.#ident = #ident;
+                #fit
+                self
+            }
+        });
+    }
+    if versioned {
+        out.extend(gen_version(class, model));
+    }
+    if out.is_empty() {
+        return out;
+    }
+    quote! {
+        impl #name {
+            #out
+        }
+    }
+}
+
+// The version of a box that can hold the values of the fields. Only
+// the fields that depend on the version are checked: their value must
+// fit in the width they have in that version, or be the default if they
+// are not there at all.
+fn gen_version(class: &Class, model: &Model) -> TokenStream {
+    let field = model.version_field().unwrap();
+    let ident = &field.ident;
+    let ty = field.ty.tokens();
+    let versions: Vec<_> = model.versions.iter().map(|&v| quote!(#v as #ty)).collect();
+
+    // The declarations in every version.
+    let mut parents = HashMap::new();
+    let decls: Vec<Decls> = model.versions.iter().map(|&v| {
+        let mut decls = Decls::new();
+        version_decls(model, &class.body.statements, 0, v, &mut parents, &mut decls);
+        decls
+    }).collect();
+    let mut keys: Vec<_> = decls.iter().flat_map(|d| d.keys().cloned()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut arms = Vec::new();
+    for (&v, vdecls) in model.versions.iter().zip(&decls) {
+        let mut checks = Vec::new();
+        for key in &keys {
+            if decls.iter().all(|d| d.get(key) == vdecls.get(key)) {
+                continue;
+            }
+            let (level, ref name) = *key;
+            let field = match model.struct_fields(level).find(|f| &f.name == name) {
+                Some(field) => field,
+                None => match model.levels[level].fields.iter().find(|f| &f.name == name) {
+                    Some(field) if field.derived.is_some() => field,
+                    _ => continue,
+                },
+            };
+            let check = match (vdecls.get(key), &field.ty) {
+                (None, _) if field.derived.is_some() => quote!(*__x == 0),
+                (None, _) if field.optional => quote!(__x.is_none()),
+                (None, Type::Vec(_)) => quote!(__x.is_empty()),
+                (None, ty) => {
+                    let ty = ty.tokens();
+                    quote!(*__x == <#ty as ::std::default::Default>::default())
+                },
+                (Some(&Type::Int{ bits, signed }), &Type::Int{ .. }) => {
+                    let (min, max) = int_range(bits, signed);
+                    let fits = quote!((#min ..= #max).contains(&(*__x as i128)));
+                    if field.optional && field.derived.is_none() {
+                        quote!(__x.iter().all(|__x| #fits))
+                    } else {
+                        fits
+                    }
+                },
+                _ => continue,
+            };
+            checks.push(for_all(model, &parents, level, field, check));
+        }
+        arms.push(quote!(#v => true #(&& #checks)*,));
+    }
+
+    quote! {
+        /// The smallest version of the box that can hold the values of its fields.
+        pub fn min_version(&self) -> #ty {
+            [#(#versions),*].iter().copied().find(|&__v| self.holds_version(__v)).unwrap_or(self.#ident)
+        }
+
+        // The version of the box, raised to one that can hold the values
+        // of its fields if it cannot.
+        fn fitting_version(&self) -> #ty {
+            if self.holds_version(self.#ident) {
+                return self.#ident;
+            }
+            [#(#versions),*].iter().copied()
+                .find(|&__v| __v > self.#ident && self.holds_version(__v))
+                .unwrap_or(self.#ident)
+        }
+
+        // Raise the version if the fields do not fit.
+        fn fit_version(&mut self) {
+            self.#ident = self.fitting_version();
+        }
+
+        // Can the fields be written as version `v`.
+        #[allow(clippy::match_single_binding, clippy::nonminimal_bool)]
+        fn holds_version(&self, __v: #ty) -> bool {
+            match __v as i64 {
+                #(#arms)*
+                _ => true,
+            }
+        }
+    }
+}
+
+// The type of the fields that are declared in a version, by level and name.
+type Decls = HashMap<(usize, String), Type>;
+
+// The declarations in a version. Conditions that do not only depend on
+// the version can go either way. `parents` gets the level that holds
+// the entries of every loop.
+fn version_decls(model: &Model, stmts: &Stmts, level: usize, version: i64, parents: &mut HashMap<usize, usize>, decls: &mut Decls) {
+    for stmt in &stmts.0 {
+        match stmt {
+            Stmt::VarDecl(decl) => {
+                if model::is_hidden(decl) {
+                    continue;
+                }
+                let mut ty = Type::from_decl(decl);
+                if decl.array.is_some() {
+                    ty = Type::Vec(Box::new(ty));
+                }
+                let key = (level, model::decl_name(decl));
+                let ty = match decls.get(&key) {
+                    Some(prev) => prev.merge(&ty).unwrap_or(ty),
+                    None => ty,
+                };
+                decls.insert(key, ty);
+            },
+            Stmt::If(ifelse) => {
+                let branches = std::iter::once((Some(&ifelse.ifexpr), &ifelse.if_true))
+                    .chain(ifelse.if_else.iter().map(|i| (Some(&i.ifexpr), &i.if_true)))
+                    .chain(std::iter::once((None, &ifelse.if_false)));
+                for (cond, body) in branches {
+                    let value = cond.map(|c| eval(c, version));
+                    if value == Some(Some(0)) {
+                        continue;
+                    }
+                    version_decls(model, body, level, version, parents, decls);
+                    // This one is taken for sure.
+                    if value != Some(None) {
+                        break;
+                    }
+                }
+            },
+            Stmt::For(f) => {
+                let idx = model.loops[&(f as *const For)];
+                parents.insert(idx, level);
+                version_decls(model, &f.statements, idx, version, parents, decls);
+            },
+        }
+    }
+}
+
+// The value of a condition, if it only depends on the version.
+fn eval(e: &Expr, version: i64) -> Option<i64> {
+    match e {
+        Expr::LitInt(lit) => lit.base10_parse().ok(),
+        Expr::Variable(v) if v == "version" => Some(version),
+        Expr::Paren{ expr, .. } => eval(expr, version),
+        Expr::Binary{ left, op, right } => {
+            let (l, r) = (eval(left, version), eval(right, version));
+            match *op {
+                BinOp::AndAnd(_) if l == Some(0) || r == Some(0) => return Some(0),
+                BinOp::OrOr(_) if l.is_some_and(|l| l != 0) || r.is_some_and(|r| r != 0) => return Some(1),
+                _ => {},
+            }
+            let (l, r) = (l?, r?);
+            Some(match *op {
+                BinOp::EqEq(_) => (l == r) as i64,
+                BinOp::Ne(_) => (l != r) as i64,
+                BinOp::Lt(_) => (l < r) as i64,
+                BinOp::Le(_) => (l <= r) as i64,
+                BinOp::Gt(_) => (l > r) as i64,
+                BinOp::Ge(_) => (l >= r) as i64,
+                BinOp::AndAnd(_) | BinOp::OrOr(_) => 1,
+                BinOp::And(_) => l & r,
+                BinOp::Add(_) => l.wrapping_add(r),
+                BinOp::Sub(_) => l.wrapping_sub(r),
+                _ => return None,
+            })
+        },
+        _ => None,
+    }
+}
+
+// The values of an int of `bits` bits.
+fn int_range(bits: u32, signed: bool) -> (i128, i128) {
+    match signed {
+        true => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+        false => (0, (1i128 << bits) - 1),
+    }
+}
+
+// Check a field in all the entries of its level, with `__x` the value.
+fn for_all(model: &Model, parents: &HashMap<usize, usize>, level: usize, field: &Field, check: TokenStream) -> TokenStream {
+    let ident = &field.ident;
+    if level == 0 {
+        return match field.derived {
+            Some((ref target, offset)) => quote!({ let __x = &(self.#target.len() as i64 + #offset); #check }),
+            None => quote!({ let __x = &self.#ident; #check }),
+        };
+    }
+    let entries = entries(model, parents, level);
+    if model.levels[level].inline {
+        return quote!(#entries.all(|__x| #check));
+    }
+    match field.derived {
+        Some((ref target, offset)) => quote!(#entries.all(|__e| { let __x = &(__e.#target.len() as i64 + #offset); #check })),
+        None => quote!(#entries.all(|__e| { let __x = &__e.#ident; #check })),
+    }
+}
+
+// An iterator over the entries of a loop.
+fn entries(model: &Model, parents: &HashMap<usize, usize>, level: usize) -> TokenStream {
+    let field = model.loop_field(level);
+    match parents[&level] {
+        0 => quote!(self.#field.iter()),
+        parent => {
+            let outer = entries(model, parents, parent);
+            quote!(#outer.flat_map(|__e| __e.#field.iter()))
+        },
+    }
+}
@@ -0,0 +1,406 @@
+// Code generation from the lowered model.
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::Ident;
+
+use crate::{Class, Init};
+use crate::expr::{BinOp, Expr};
+use crate::model::{self, Field, Model, Root, Type, Value};
+
+// Generate all the code for a class.
+pub fn generate(class: &Class, model: &Model) -> TokenStream {
+    let mut out = TokenStream::new();
+
+    let doc = match (&model.fourcc, model.name.to_string()) {
+        (&Value::Lit(Expr::LitStr(ref s)), name) => format!(" The `{}` box (`{}`).", s.value(), name),
+        (_, name) if model.is_abstract => format!(" The abstract `{}` class.", name),
+        (_, name) => format!(" The `{}` class.", name),
+    };
+    out.extend(gen_struct(model, 0, &model.name, &doc));
+    out.extend(gen_default(model, 0, &model.name));
+
+    for (idx, level) in model.entries() {
+        let name = Ident::new(&level.name, Span::call_site());
+        let doc = format!(" An entry in `{}::{}`.", model.name, level.plural);
+        out.extend(gen_struct(model, idx, &name, &doc));
+        out.extend(gen_default(model, idx, &name));
+    }
+
+    out.extend(gen_info(model));
+    out.extend(crate::read::gen_read(class, model));
+    out.extend(crate::write::gen_write(class, model));
+    out.extend(crate::builder::gen_builder(class, model));
+
+    out
+}
+
+// The struct for a level.
+fn gen_struct(model: &Model, level: usize, name: &Ident, doc: &str) -> TokenStream {
+    let fields = model.struct_fields(level).map(|f| {
+        let ident = &f.ident;
+        let ty = f.tokens();
+        quote!(pub #ident: #ty)
+    });
+    let allow = if name.to_string().starts_with(char::is_lowercase) {
+        quote!(#[allow(non_camel_case_types)])
+    } else {
+        quote!()
+    };
+    let derive = if has_defaults(model, level) {
+        quote!(#[derive(Clone, Debug, PartialEq)])
+    } else {
+        quote!(#[derive(Clone, Debug, Default, PartialEq)])
+    };
+    quote! {
+        #[doc = #doc]
+        #derive
+        #allow
+        pub struct #name {
+            #(#fields,)*
+        }
+    }
+}
+
+// Does a level have fields with a default value.
+fn has_defaults(model: &Model, level: usize) -> bool {
+    model.struct_fields(level).any(|f| f.default.is_some())
+}
+
+// `Default` for a level with fields that have a default value, like
+// the unity matrix of `tkhd`. If a default depends on variables that
+// are not part of the class, like `track_is_audio`, there is also a
+// `default_in` that takes their values from a `Context`.
+fn gen_default(model: &Model, level: usize, name: &Ident) -> TokenStream {
+    if !has_defaults(model, level) {
+        return quote!();
+    }
+    let scope = Scope::defaults(model, level);
+    let mut externals = Vec::new();
+    let mut locals = TokenStream::new();
+    for f in model.struct_fields(level) {
+        let ident = &f.ident;
+        let ty = f.tokens();
+        let value = match f.default {
+            Some(ref init) if !f.optional => {
+                let mut vars = Vec::new();
+                for e in init.exprs() {
+                    e.variables(&mut vars);
+                }
+                for v in vars.iter().map(|v| v.to_string()) {
+                    let header = model.root == Root::FullBox && (v == "version" || v == "flags");
+                    if !header && model.field(&scope.levels, &v).is_none() && !externals.contains(&v) {
+                        externals.push(v);
+                    }
+                }
+                default_value(&scope, f, init)
+            },
+            _ => quote!(Default::default()),
+        };
+        locals.extend(quote!(let #ident: #ty = #value;));
+    }
+    let fields = model.struct_fields(level).map(|f| &f.ident);
+    let (version, flags) = match (&model.version, &model.flags) {
+        (Value::Lit(v), Value::Lit(f)) if level == 0 => (scope.expr_int(v), scope.expr_int(f)),
+        (Value::Lit(v), _) if level == 0 => (scope.expr_int(v), quote!(0)),
+        _ => (quote!(0), quote!(0)),
+    };
+    let body = quote! {
+        let __version = #version as u8;
+        let __flags = #flags as u32;
+        #locals
+        #name {
+            #(#fields,)*
+        }
+    };
+
+    if externals.is_empty() {
+        return quote! {
+            impl Default for #name {
+                #[allow(unused_variables, unused_parens)]
+                fn default() -> #name {
+                    #body
+                }
+            }
+        };
+    }
+    let vars = externals.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>().join(", ");
+    let doc = format!(" A `{}` with the default values, which depend on {} in `ctx`.", name, vars);
+    quote! {
+        impl #name {
+            #[doc = #doc]
+            #[allow(unused_variables, unused_parens)]
+            pub fn default_in(__ctx: &::iso_bmff::Context) -> #name {
+                #body
+            }
+        }
+
+        impl Default for #name {
+            fn default() -> #name {
+                #name::default_in(&::iso_bmff::Context::new())
+            }
+        }
+    }
+}
+
+// The default value of a field.
+fn default_value(scope: &Scope, field: &Field, init: &Init) -> TokenStream {
+    let cast = |e: &Expr, ty: &Type| {
+        let v = scope.expr_int(e);
+        match ty {
+            &Type::Int{..} | &Type::Flags => {
+                let t = ty.tokens();
+                quote!((#v as #t))
+            },
+            &Type::Bool => quote!((#v != 0)),
+            // the value as it is in the file.
+            &Type::Rust(_) => {
+                let t = ty.tokens();
+                quote!(<#t>::from_raw(#v as _))
+            },
+            _ => quote!(Default::default()),
+        }
+    };
+    match (&field.ty, init) {
+        (Type::Array(t, _), Init::List(list)) => {
+            let elems = list.iter().map(|e| cast(e, t));
+            quote!([#(#elems),*])
+        },
+        (Type::Array(t, size), Init::Value(e)) => {
+            let v = cast(e, t);
+            let size = *size as usize;
+            quote!([#v; #size])
+        },
+        (ty, Init::Value(e)) => cast(e, ty),
+        _ => quote!(Default::default()),
+    }
+}
+
+// Boxes with a fixed fourcc can be part of a `BoxNode`.
+fn gen_info(model: &Model) -> TokenStream {
+    let fourcc = match model.fourcc {
+        Value::Lit(ref e) if !model.is_abstract && (model.root == Root::Box || model.root == Root::FullBox) => fourcc(e),
+        _ => None,
+    };
+    let fourcc = match fourcc {
+        Some(fourcc) => fourcc,
+        None => return quote!(),
+    };
+    let name = &model.name;
+    let usertype = match model.usertype {
+        Some(u) => quote!(const USERTYPE: Option<::iso_bmff::Uuid> = Some(::iso_bmff::Uuid([#(#u),*]));),
+        None => quote!(),
+    };
+    let children = if model.container {
+        quote! {
+            fn children(&self) -> Option<&[::iso_bmff::BoxNode]> {
+                Some(&self.boxes)
+            }
+        }
+    } else {
+        quote!()
+    };
+    quote! {
+        impl ::iso_bmff::BoxInfo for #name {
+            const FOURCC: ::iso_bmff::FourCC = #fourcc;
+            #usertype
+            #children
+        }
+    }
+}
+
+// The variables that are visible at some point in the generated code.
+pub struct Scope<'a> {
+    pub model:  &'a Model,
+    // the levels we are in, outermost first.
+    pub levels: Vec<usize>,
+    // the reader or writer.
+    ctx:        Ident,
+    // when writing, the variables are references.
+    deref:      bool,
+    // variables that are not known are an error, not a default.
+    fallible:   bool,
+}
+
+impl<'a> Scope<'a> {
+    pub fn reader(model: &'a Model) -> Scope<'a> {
+        Scope {
+            model,
+            levels: vec![ 0 ],
+            ctx:    Ident::new("__r", Span::call_site()),
+            deref:  false,
+            fallible: true,
+        }
+    }
+
+    pub fn writer(model: &'a Model) -> Scope<'a> {
+        Scope {
+            model,
+            levels: vec![ 0 ],
+            ctx:    Ident::new("__w", Span::call_site()),
+            deref:  true,
+            fallible: true,
+        }
+    }
+
+    // For the default values of the fields of a level.
+    pub fn defaults(model: &'a Model, level: usize) -> Scope<'a> {
+        Scope {
+            model,
+            levels: vec![ level ],
+            ctx:    Ident::new("__ctx", Span::call_site()),
+            deref:  false,
+            fallible: false,
+        }
+    }
+
+    // `# export: name`: make the value of a field known to the boxes
+    // inside, through `set_var` of `rw`, the reader or the writer.
+    pub fn export(&self, field: &Field, rw: TokenStream) -> TokenStream {
+        if field.export.is_empty() {
+            return quote!();
+        }
+        let value = self.var(&Ident::new(&field.name, Span::call_site()));
+        let names = &field.export;
+        quote!(#(#rw.set_var(#names, #value);)*)
+    }
+
+    // A variable that is not part of the class.
+    fn external(&self, name: &str) -> TokenStream {
+        if let Some(&(_, value)) = model::CONSTANTS.iter().find(|c| c.0 == name) {
+            return quote!(#value);
+        }
+        let ctx = &self.ctx;
+        if self.fallible {
+            quote!(#ctx.lookup(#name)?)
+        } else {
+            quote!(#ctx.get(#name).unwrap_or(0))
+        }
+    }
+
+    // The value of a variable, as an i64.
+    fn var(&self, name: &Ident) -> TokenStream {
+        let n = name.to_string();
+        let field = match self.model.field(&self.levels, &n) {
+            Some(field) => field,
+            None => {
+                // version and flags of a FullBox are always known.
+                if self.model.root == Root::FullBox && (n == "version" || n == "flags") {
+                    let v = Ident::new(&format!("__{}", n), Span::call_site());
+                    return quote!((#v as i64));
+                }
+                return self.external(&n);
+            },
+        };
+        let ident = &field.ident;
+        let ident = if self.deref { quote!((*#ident)) } else { quote!(#ident) };
+        if self.deref && field.derived.is_some() {
+            // computed from the length of a Vec, already an i64.
+            return quote!(#ident);
+        }
+        match (&field.ty, field.optional) {
+            (&Type::Int{..}, false) | (&Type::Bool, false) | (&Type::Flags, false) => quote!((#ident as i64)),
+            (&Type::Int{..}, true) | (&Type::Bool, true) | (&Type::Flags, true) => {
+                quote!((#ident.unwrap_or_default() as i64))
+            },
+            (&Type::Vec(_), _) => quote!((#ident.len() as i64)),
+            // like typed flags, which convert to an integer.
+            (&Type::Rust(_), false) => quote!((#ident.to_raw() as i64)),
+            (&Type::Rust(_), true) => quote!((#ident.map(|v| v.to_raw() as i64).unwrap_or(0))),
+            _ => self.external(&n),
+        }
+    }
+
+    // An expression as an i64.
+    pub fn expr_int(&self, expr: &Expr) -> TokenStream {
+        match expr {
+            Expr::LitInt(lit) => match lit.base10_parse::<i64>() {
+                Ok(v) => quote!(#v),
+                Err(e) => e.to_compile_error(),
+            },
+            Expr::LitStr(lit) => {
+                // a fourcc.
+                let v = lit.value().bytes().fold(0i64, |v, b| (v << 8) | b as i64);
+                quote!(#v)
+            },
+            Expr::Variable(name) => self.var(name),
+            Expr::Paren{ expr, .. } => self.expr_int(expr),
+            Expr::Binary{ left, op, right } => {
+                let l = self.expr_int(left);
+                let r = self.expr_int(right);
+                match *op {
+                    BinOp::Add(_) => quote!(#l.wrapping_add(#r)),
+                    BinOp::Sub(_) => quote!(#l.wrapping_sub(#r)),
+                    BinOp::Star(_) => quote!(#l.wrapping_mul(#r)),
+                    BinOp::Div(_) => quote!(#l.checked_div(#r).unwrap_or(0)),
+                    BinOp::Shl(_) => quote!(#l.wrapping_shl(#r as u32)),
+                    BinOp::Shr(_) => quote!(#l.wrapping_shr(#r as u32)),
+                    BinOp::And(_) => quote!((#l & #r)),
+                    _ => {
+                        let b = self.expr_bool(expr);
+                        quote!((#b as i64))
+                    },
+                }
+            },
+            Expr::Cond{ cond, then, otherwise, .. } => {
+                let c = self.expr_bool(cond);
+                let a = self.expr_int(then);
+                let b = self.expr_int(otherwise);
+                quote!((if #c { #a } else { #b }))
+            },
+        }
+    }
+
+    // An expression as a bool.
+    pub fn expr_bool(&self, expr: &Expr) -> TokenStream {
+        match expr {
+            Expr::Paren{ expr, .. } => self.expr_bool(expr),
+            Expr::Binary{ left, op, right } => {
+                let cmp = |op: TokenStream| {
+                    let l = self.expr_int(left);
+                    let r = self.expr_int(right);
+                    quote!((#l #op #r))
+                };
+                match *op {
+                    BinOp::EqEq(_) => cmp(quote!(==)),
+                    BinOp::Ne(_) => cmp(quote!(!=)),
+                    BinOp::Lt(_) => cmp(quote!(<)),
+                    BinOp::Le(_) => cmp(quote!(<=)),
+                    BinOp::Gt(_) => cmp(quote!(>)),
+                    BinOp::Ge(_) => cmp(quote!(>=)),
+                    BinOp::AndAnd(_) => {
+                        let l = self.expr_bool(left);
+                        let r = self.expr_bool(right);
+                        quote!((#l && #r))
+                    },
+                    BinOp::OrOr(_) => {
+                        let l = self.expr_bool(left);
+                        let r = self.expr_bool(right);
+                        quote!((#l || #r))
+                    },
+                    _ => {
+                        let v = self.expr_int(expr);
+                        quote!((#v != 0))
+                    },
+                }
+            },
+            other => {
+                let v = self.expr_int(other);
+                quote!((#v != 0))
+            },
+        }
+    }
+}
+
+// `::iso_bmff::FourCC` for the fourcc of a box.
+pub fn fourcc(expr: &Expr) -> Option<TokenStream> {
+    match expr {
+        Expr::LitStr(lit) if lit.value().len() == 4 => Some(fourcc_str(&lit.value())),
+        _ => None,
+    }
+}
+
+// A fourcc from a string of 4 characters.
+pub fn fourcc_str(s: &str) -> TokenStream {
+    let v = s.bytes().fold(0u32, |v, b| (v << 8) | b as u32);
+    quote!(::iso_bmff::FourCC(#v))
+}
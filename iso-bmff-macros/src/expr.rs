@@ -8,6 +8,7 @@ use quote::ToTokens;
 
 macro_rules! def_binop {
     ($($op:ident),+) => {
+        #[derive(Clone)]
         pub enum BinOp {
             $(
                 $op(token::$op),
@@ -47,9 +48,12 @@ macro_rules! def_binop {
     }
 }
 
-def_binop!{Add, Sub, Star, Div, And, OrOr, Le, Lt, Ge, Gt, EqEq, Ne, Shl, Shr}
+// Note: operators that are a prefix of another operator (`<` of `<<`, `&` of `&&`)
+// must come after that operator, since they are peeked in this order.
+def_binop!{Shl, Shr, Le, Ge, EqEq, Ne, AndAnd, OrOr, Add, Sub, Star, Div, And, Lt, Gt}
 
 // Expression.
+#[derive(Clone)]
 pub enum Expr {
     // "foo"
     LitStr(syn::LitStr),
@@ -68,6 +72,14 @@ pub enum Expr {
         op:     BinOp,
         right:  Box<Expr>,
     },
+    // if a b else c, in a default value like `{if track_is_audio 0x0100 else 0}`.
+    Cond {
+        if_token:   token::If,
+        cond:       Box<Expr>,
+        then:       Box<Expr>,
+        else_token: token::Else,
+        otherwise:  Box<Expr>,
+    },
 }
 
 impl Expr {
@@ -77,27 +89,99 @@ impl Expr {
 
     #[allow(dead_code)]
     pub fn get_lit_int(&self) -> Result<u32> {
-        if let &Expr::LitInt(ref lit_int) = self {
+        if let Expr::LitInt(lit_int) = self {
             return lit_int.base10_parse::<u32>();
         }
         Err(Error::new(self.span(), "expected number"))
     }
+
+    // The expression without the parentheses around it.
+    pub fn strip_parens(&self) -> &Expr {
+        match self {
+            Expr::Paren{ expr, .. } => expr.strip_parens(),
+            other => other,
+        }
+    }
+
+    // All variables used in this expression.
+    pub fn variables(&self, vars: &mut Vec<Ident>) {
+        match self {
+            Expr::Variable(v) => vars.push(v.clone()),
+            Expr::Paren{ expr, .. } => expr.variables(vars),
+            Expr::Binary{ left, right, .. } => {
+                left.variables(vars);
+                right.variables(vars);
+            },
+            Expr::Cond{ cond, then, otherwise, .. } => {
+                cond.variables(vars);
+                then.variables(vars);
+                otherwise.variables(vars);
+            },
+            _ => {},
+        }
+    }
+
+    // The values that a variable is compared with, like 1 in `version == 1`.
+    pub fn compared_values(&self, name: &str, values: &mut Vec<i64>) {
+        match self {
+            Expr::Paren{ expr, .. } => expr.compared_values(name, values),
+            Expr::Binary{ left, right, .. } => {
+                match (left.strip_parens(), right.strip_parens()) {
+                    (&Expr::Variable(ref v), &Expr::LitInt(ref lit)) |
+                    (&Expr::LitInt(ref lit), &Expr::Variable(ref v)) if v == name => {
+                        values.extend(lit.base10_parse::<i64>().ok());
+                    },
+                    _ => {
+                        left.compared_values(name, values);
+                        right.compared_values(name, values);
+                    },
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Replace a variable by another expression.
+    pub fn subst(&mut self, name: &str, with: &Expr) {
+        match *self {
+            Expr::Variable(ref v) if v == name => {},
+            Expr::Paren{ ref mut expr, .. } => return expr.subst(name, with),
+            Expr::Binary{ ref mut left, ref mut right, .. } => {
+                left.subst(name, with);
+                return right.subst(name, with);
+            },
+            Expr::Cond{ ref mut cond, ref mut then, ref mut otherwise, .. } => {
+                cond.subst(name, with);
+                then.subst(name, with);
+                return otherwise.subst(name, with);
+            },
+            _ => return,
+        }
+        *self = with.clone();
+    }
 }
 
 impl std::fmt::Debug for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            &Expr::LitInt(ref t) => write!(f, "Expr::LitInt({})", t.to_string()),
-            &Expr::LitStr(ref t) => write!(f, "Expr::LitStr({})", t.value()),
-            &Expr::Paren{ref expr, ..} => f.debug_tuple("Expr::Paren").field(expr).finish(),
-            &Expr::Variable(ref t) => write!(f, "Expr::Variable({})", t.to_string()),
-            &Expr::Binary{ref left, ref op, ref right} => {
+            Expr::LitInt(t) => write!(f, "Expr::LitInt({})", t),
+            Expr::LitStr(t) => write!(f, "Expr::LitStr({})", t.value()),
+            Expr::Paren{expr, ..} => f.debug_tuple("Expr::Paren").field(expr).finish(),
+            Expr::Variable(t) => write!(f, "Expr::Variable({})", t),
+            Expr::Binary{left, op, right} => {
                 f.debug_struct("Expr::Binary")
                     .field("left", left)
                     .field("op", op)
                     .field("right", right)
                     .finish()
-            }
+            },
+            Expr::Cond{cond, then, otherwise, ..} => {
+                f.debug_struct("Expr::Cond")
+                    .field("cond", cond)
+                    .field("then", then)
+                    .field("otherwise", otherwise)
+                    .finish()
+            },
         }
     }
 }
@@ -105,19 +189,26 @@ impl std::fmt::Debug for Expr {
 impl ToTokens for Expr {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         match self {
-            &Expr::LitStr(ref t) => t.to_tokens(tokens),
-            &Expr::LitInt(ref t) => t.to_tokens(tokens),
-            &Expr::Variable(ref t) => t.to_tokens(tokens),
-            &Expr::Paren{ref paren_token, ref expr} => {
+            Expr::LitStr(t) => t.to_tokens(tokens),
+            Expr::LitInt(t) => t.to_tokens(tokens),
+            Expr::Variable(t) => t.to_tokens(tokens),
+            Expr::Paren{paren_token, expr} => {
                 paren_token.surround(tokens, |tokens| {
                     expr.to_tokens(tokens);
                 })
             },
-            &Expr::Binary{ref left, ref op, ref right} => {
+            Expr::Binary{left, op, right} => {
                 left.to_tokens(tokens);
                 op.to_tokens(tokens);
                 right.to_tokens(tokens);
-            }
+            },
+            Expr::Cond{if_token, cond, then, else_token, otherwise} => {
+                if_token.to_tokens(tokens);
+                cond.to_tokens(tokens);
+                then.to_tokens(tokens);
+                else_token.to_tokens(tokens);
+                otherwise.to_tokens(tokens);
+            },
         }
     }
 }
@@ -125,34 +216,74 @@ impl ToTokens for Expr {
 
 impl Parse for Expr {
     fn parse(input: ParseStream) -> Result<Self> {
-        // First check for some kind of value.
+        Expr::parse_binary(input, 0)
+    }
+}
+
+impl BinOp {
+    // Operator precedence, as in C.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            &BinOp::Star(_) | &BinOp::Div(_) => 10,
+            &BinOp::Add(_) | &BinOp::Sub(_) => 9,
+            &BinOp::Shl(_) | &BinOp::Shr(_) => 8,
+            &BinOp::Lt(_) | &BinOp::Le(_) | &BinOp::Gt(_) | &BinOp::Ge(_) => 7,
+            &BinOp::EqEq(_) | &BinOp::Ne(_) => 6,
+            &BinOp::And(_) => 5,
+            &BinOp::AndAnd(_) => 3,
+            &BinOp::OrOr(_) => 2,
+        }
+    }
+}
+
+impl Expr {
+    // A value: literal, variable, or expression between parentheses.
+    fn parse_primary(input: ParseStream) -> Result<Self> {
         let lookahead = input.lookahead1();
-        let left = if lookahead.peek(token::Paren) {
+        if lookahead.peek(token::If) {
+            Ok(Expr::Cond {
+                if_token:   input.parse()?,
+                cond:       Box::new(input.parse()?),
+                then:       Box::new(input.parse()?),
+                else_token: input.parse()?,
+                otherwise:  Box::new(input.parse()?),
+            })
+        } else if lookahead.peek(token::Paren) {
             let content;
             let paren_token = parenthesized!(content in input);
             let expr = Box::new(content.parse()?);
-            Expr::Paren{ paren_token, expr }
+            Ok(Expr::Paren{ paren_token, expr })
         } else if lookahead.peek(syn::LitStr) {
-            Expr::LitStr(input.parse()?)
+            Ok(Expr::LitStr(input.parse()?))
         } else if lookahead.peek(syn::LitInt) {
-            Expr::LitInt(input.parse()?)
+            // Values are i64 in the generated code.
+            let lit: syn::LitInt = input.parse()?;
+            lit.base10_parse::<i64>()?;
+            Ok(Expr::LitInt(lit))
         } else if lookahead.peek(Ident::peek_any) {
-            Expr::Variable(input.call(Ident::parse_any)?)
+            Ok(Expr::Variable(input.call(Ident::parse_any)?))
         } else {
-            return Err(lookahead.error());
-        };
-        // not followed by an operator? End of expression.
-        if !BinOp::peek(&input) {
-            return Ok(left);
+            Err(lookahead.error())
         }
-        // Operator, and an expression on the right hand side.
-        let op = BinOp::parse(&input)?;
-        let right: Expr = input.parse()?;
-        Ok(Expr::Binary{
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        })
     }
-}
 
+    // Values with operators in between, binding operators with a
+    // higher precedence first ("precedence climbing").
+    fn parse_binary(input: ParseStream, min_prec: u8) -> Result<Self> {
+        let mut left = Expr::parse_primary(input)?;
+        while BinOp::peek(input) {
+            let prec = BinOp::parse(&input.fork())?.precedence();
+            if prec < min_prec {
+                break;
+            }
+            let op = BinOp::parse(input)?;
+            let right = Expr::parse_binary(input, prec + 1)?;
+            left = Expr::Binary{
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+}
@@ -0,0 +1,179 @@
+// The list of all boxes, for `BoxNode`.
+//
+// `box_index!(box_node, sample_group_entry, "src/spec.rs", "src/piff.rs")`
+// reads the `def_box!` classes in those files, and calls the first macro
+// with the boxes that a `BoxNode` can hold, in the order they are tried:
+//
+//     box_node! { PiffSampleEncryptionBox, FileTypeBox, .. FreeSpaceBox(free_type), }
+//
+// A box with a fourcc that is a variable comes with the field that
+// holds it. It is only part of the list if its `Placement` says where
+// it can be. The boxes that can be in fewer places come first.
+//
+// The classes that are not a box, but that are placed, like the entries
+// of a `sgpd` box, go to the second macro in the same way.
+//
+// The files are read when the macro is expanded, so it also includes
+// them with `include_str!`, which makes them inputs of the build.
+//
+// The `__def_box_Name` macros of the classes, see inherit.rs, are only
+// visible in this crate. It also exports a copy of each of them, so
+// that other crates can extend the classes.
+use std::collections::HashMap;
+use std::path::Path;
+
+use proc_macro2::TokenStream;
+use quote::{quote, format_ident};
+use syn::{Ident, LitStr, Token, Result, Error};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+
+use crate::Class;
+use crate::inherit;
+use crate::model::{Model, Root, Value};
+
+// The input of `box_index!`.
+pub struct Input {
+    boxes:      Ident,
+    entries:    Ident,
+    files:      Vec<LitStr>,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let boxes = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let entries = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let files: Punctuated<LitStr, Token![,]> = input.parse_terminated(|i| i.parse())?;
+        Ok(Input {
+            boxes,
+            entries,
+            files: files.into_iter().collect(),
+        })
+    }
+}
+
+pub fn expand(input: Input) -> Result<TokenStream> {
+    let classes = read_classes(&input.files)?;
+    let index: HashMap<&str, &TokenStream> = classes.iter().map(|(n, t)| (n.as_str(), t)).collect();
+
+    let mut boxes = Vec::new();
+    let mut entries = Vec::new();
+    for (idx, (_, tokens)) in classes.iter().enumerate() {
+        // Errors in a class are reported by its `def_box!`.
+        let model = match flatten(&index, tokens.clone()).and_then(|c| Model::lower(&c)) {
+            Ok(model) => model,
+            Err(_) => continue,
+        };
+        if model.is_abstract {
+            continue;
+        }
+        let placement = &model.placement;
+        let placed = !placement.parent.is_empty() || !placement.handler.is_empty();
+        let name = &model.name;
+        if model.root != Root::Box && model.root != Root::FullBox {
+            if placed {
+                entries.push(((model.bound.is_empty(), idx), quote!(#name)));
+            }
+            continue;
+        }
+        let (entry, var) = match model.fourcc {
+            Value::Lit(_) => (quote!(#name), false),
+            Value::Var(ref v) if !placement.is_empty() => {
+                let field = match model.field(&[0], v) {
+                    Some(field) => &field.ident,
+                    None => continue,
+                };
+                (quote!(#name(#field)), true)
+            },
+            _ => continue,
+        };
+        let order = (model.usertype.is_none(), !placed, var, placement.fourcc.is_empty(), idx);
+        boxes.push((order, entry));
+    }
+    boxes.sort_by_key(|a| a.0);
+    entries.sort_by_key(|a| a.0);
+
+    let (boxes_mac, entries_mac) = (&input.boxes, &input.entries);
+    let boxes = boxes.into_iter().map(|b| b.1);
+    let entries = entries.into_iter().map(|e| e.1);
+    let exports = classes.iter().map(|(name, tokens)| {
+        let mac = format_ident!("__def_box_{}", name);
+        quote! {
+            #[doc(hidden)]
+            #[macro_export]
+            macro_rules! #mac {
+                ($($t:tt)*) => {
+                    ::iso_bmff::def_box! { @parent { #tokens } $($t)* }
+                };
+            }
+        }
+    });
+    let files = input.files.iter().map(|file| {
+        let path = format!("/{}", file.value());
+        quote!(const _: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), #path));)
+    });
+    Ok(quote! {
+        #(#files)*
+        #boxes_mac! {
+            #(#boxes,)*
+        }
+        #entries_mac! {
+            #(#entries,)*
+        }
+        #(#exports)*
+    })
+}
+
+// The classes in the files, in order, as the name and the input of `def_box!`.
+fn read_classes(files: &[LitStr]) -> Result<Vec<(String, TokenStream)>> {
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let mut classes = Vec::new();
+    for file in files {
+        let path = Path::new(&dir).join(file.value());
+        let ast = parse_file(&path).map_err(|e| Error::new(file.span(), e))?;
+        file_classes(&ast.items, &mut classes);
+    }
+    Ok(classes)
+}
+
+fn parse_file(path: &Path) -> std::result::Result<syn::File, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    syn::parse_file(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// The `def_box!` classes in these items, and in the modules in them.
+fn file_classes(items: &[syn::Item], classes: &mut Vec<(String, TokenStream)>) {
+    for item in items {
+        let mac = match item {
+            syn::Item::Macro(m) => &m.mac,
+            syn::Item::Mod(m) => {
+                if let Some((_, ref items)) = m.content {
+                    file_classes(items, classes);
+                }
+                continue;
+            },
+            _ => continue,
+        };
+        if mac.path.segments.last().is_none_or(|s| s.ident != "def_box") {
+            continue;
+        }
+        if let Ok(class) = syn::parse2::<Class>(mac.tokens.clone()) {
+            classes.push((class.head.name.to_string(), mac.tokens.clone()));
+        }
+    }
+}
+
+// A class, merged with the classes it extends.
+fn flatten(index: &HashMap<&str, &TokenStream>, tokens: TokenStream) -> Result<Class> {
+    let mut class: Class = syn::parse2(tokens)?;
+    while let Some(ext) = inherit::parent_name(&class)? {
+        let parent = match index.get(ext.class.as_str()) {
+            Some(&tokens) => syn::parse2(tokens.clone())?,
+            None => return Err(Error::new(ext.span, format!("cannot find class `{}`", ext.class))),
+        };
+        class = inherit::flatten(parent, class)?;
+    }
+    Ok(class)
+}
@@ -0,0 +1,246 @@
+// Classes that extend another class.
+//
+// Every invocation of `def_box!` stands on its own, so a class can not
+// see the definition of the class that it extends. To get at it, every
+// class also defines a macro `__def_box_Name`, that calls `def_box!`
+// again with the definition of the class in front of its input:
+//
+//     def_box! { class Child extends Parent(...) { ... } }
+//
+// expands to `__def_box_Parent! { class Child ... }`, which expands to
+//
+//     def_box! { @parent { class Parent ... } class Child ... }
+//
+// and so on, until the class at the top extends `Box`, `FullBox`, or
+// nothing at all. Then the fields of the parents are merged into the
+// class, parents first.
+//
+// The macros of the classes of `iso_bmff` are exported as well, see
+// index.rs, so that another crate that has `use iso_bmff::*;` can
+// extend them. If there is no such class, the compiler complains that
+// it can not find the macro, at the name of the class.
+use proc_macro2::TokenStream;
+use quote::{quote, format_ident};
+use syn::{braced, Ident, Token, Result, Error};
+use syn::parse::{Parse, ParseStream};
+
+use crate::{Class, Extends, ExtendsArg, Stmt, Stmts, VarDecl};
+use crate::codegen;
+use crate::expr::Expr;
+use crate::model::{self, Model};
+
+// The input of `def_box!`.
+pub struct Input {
+    // the classes that are extended, outermost first.
+    parents:    Vec<Class>,
+    class:      Class,
+    // the class as it was written, without the parents.
+    tokens:     TokenStream,
+    // the entire input.
+    all:        TokenStream,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let all = input.fork().parse::<TokenStream>()?;
+        let mut parents = Vec::new();
+        while input.peek(Token![@]) {
+            input.parse::<Token![@]>()?;
+            let kw: Ident = input.parse()?;
+            if kw != "parent" {
+                return Err(Error::new(kw.span(), "expected `parent`"));
+            }
+            let content;
+            braced!(content in input);
+            parents.push(content.parse()?);
+        }
+        let tokens = input.fork().parse::<TokenStream>()?;
+        let class = input.parse()?;
+        Ok(Input {
+            parents,
+            class,
+            tokens,
+            all,
+        })
+    }
+}
+
+// Generate the code for the input of `def_box!`.
+pub fn expand(input: Input) -> Result<TokenStream> {
+    let mut out = TokenStream::new();
+    if input.parents.is_empty() {
+        out.extend(class_macro(&input.class.head.name, &input.tokens));
+    }
+
+    // If the outermost class extends another class, get that one first.
+    let top = input.parents.first().unwrap_or(&input.class);
+    if let Some(parent) = parent_name(top)? {
+        let mac = format_ident!("__def_box_{}", parent.class, span = parent.span);
+        let all = &input.all;
+        out.extend(quote!(#mac! { #all }));
+        return Ok(out);
+    }
+
+    let mut class = input.class;
+    for parent in input.parents.into_iter().rev() {
+        class = flatten(parent, class)?;
+    }
+    let model = Model::lower(&class)?;
+    out.extend(codegen::generate(&class, &model));
+    Ok(out)
+}
+
+// The macro that passes on the definition of a class.
+fn class_macro(name: &Ident, tokens: &TokenStream) -> TokenStream {
+    let mac = format_ident!("__def_box_{}", name);
+    quote! {
+        #[doc(hidden)]
+        #[allow(unused_macros)]
+        macro_rules! #mac {
+            ($($t:tt)*) => {
+                ::iso_bmff::def_box! { @parent { #tokens } $($t)* }
+            };
+        }
+        #[allow(unused_imports)]
+        pub(crate) use #mac;
+    }
+}
+
+// The class that is extended, if it is not `Box` or `FullBox`.
+pub fn parent_name(class: &Class) -> Result<Option<&Extends>> {
+    let ext = match class.head.extends {
+        Some(ref ext) => ext,
+        None => return Ok(None),
+    };
+    for root in &[ "Box", "FullBox" ] {
+        if ext.class == *root {
+            return Ok(None);
+        }
+        if ext.class.eq_ignore_ascii_case(root) {
+            let msg = format!("cannot find class `{}`, did you mean `{}`?", ext.class, root);
+            return Err(Error::new(ext.span, msg));
+        }
+    }
+    Ok(Some(ext))
+}
+
+// Merge a class with the class it extends.
+pub fn flatten(mut parent: Class, mut class: Class) -> Result<Class> {
+    let ext = class.head.extends.take().unwrap();
+    let name = &parent.head.name;
+    if *name != ext.class {
+        return Err(Error::new(class.head.name.span(), format!("expected `{}`, got `{}`", ext.class, name)));
+    }
+
+    // The arguments of the parent. The spec also passes values
+    // for variables of the parent that are not declared as an
+    // argument, like `loudnessType` in `extends FullBox(loudnessType)`.
+    let mut params: Vec<String> = parent.head.args.iter().map(model::decl_name).collect();
+    if let Some(ref pext) = parent.head.extends {
+        for arg in &pext.args {
+            if let (Some(v), None) = (&arg.varname, &arg.value) {
+                let v = v.to_string();
+                if !params.contains(&v) {
+                    params.push(v);
+                }
+            }
+        }
+    }
+    if ext.args.len() > params.len() {
+        return Err(Error::new(class.head.name.span(), format!("too many arguments for `{}`", name)));
+    }
+
+    // Replace the arguments by the values that are passed. The
+    // ones that are not passed become arguments of the class.
+    let nargs = parent.head.args.len();
+    let mut args = std::mem::take(&mut parent.head.args).into_iter();
+    for (idx, param) in params.iter().enumerate() {
+        let decl = if idx < nargs { args.next() } else { None };
+        let arg = match ext.args.get(idx) {
+            Some(arg) => arg,
+            None => {
+                class.head.args.extend(decl);
+                continue;
+            },
+        };
+        let value = match (&arg.varname, &arg.value) {
+            (_, Some(e)) => e.clone(),
+            (Some(v), None) => Expr::Variable(v.clone()),
+            (None, None) => unreachable!(),
+        };
+        if let Expr::LitStr(_) | Expr::LitInt(_) = value {
+            if !class.head.bound.iter().any(|b| b.0 == *param) {
+                class.head.bound.push((param.clone(), value.clone()));
+            }
+        }
+        if let Some(ref mut pext) = parent.head.extends {
+            for a in pext.args.iter_mut() {
+                subst_arg(a, param, &value);
+            }
+        }
+        subst_stmts(&mut parent.body.statements, param, &value);
+    }
+
+    class.head.aligned = class.head.aligned.or(parent.head.aligned);
+    class.head.placement.inherit(parent.head.placement);
+    class.head.extends = parent.head.extends;
+    // The child boxes at the end of the parent, like `Box[] other_boxes`
+    // of a `MetaDataSampleEntry`, come after the fields of the class.
+    let mut body = parent.body.statements.0;
+    let mut slots = body.split_off(body.len() - model::count_slots(&body));
+    let mut rest = std::mem::take(&mut class.body.statements.0);
+    let mut own = rest.split_off(rest.len() - model::count_slots(&rest));
+    body.append(&mut rest);
+    body.append(&mut slots);
+    body.append(&mut own);
+    class.body.statements = Stmts(body);
+    Ok(class)
+}
+
+fn subst_arg(arg: &mut ExtendsArg, name: &str, value: &Expr) {
+    match (&arg.varname, &mut arg.value) {
+        (Some(v), None) if v == name => {},
+        (_, &mut Some(ref mut e)) => return e.subst(name, value),
+        _ => return,
+    }
+    match value {
+        Expr::Variable(v) => arg.varname = Some(v.clone()),
+        other => {
+            arg.varname = None;
+            arg.value = Some(other.clone());
+        },
+    }
+}
+
+fn subst_stmts(stmts: &mut Stmts, name: &str, value: &Expr) {
+    for stmt in stmts.0.iter_mut() {
+        match *stmt {
+            Stmt::VarDecl(ref mut decl) => subst_decl(decl, name, value),
+            Stmt::If(ref mut ifelse) => {
+                ifelse.ifexpr.subst(name, value);
+                subst_stmts(&mut ifelse.if_true, name, value);
+                for i in ifelse.if_else.iter_mut() {
+                    i.ifexpr.subst(name, value);
+                    subst_stmts(&mut i.if_true, name, value);
+                }
+                subst_stmts(&mut ifelse.if_false, name, value);
+            },
+            Stmt::For(ref mut f) => {
+                if let Some(ref mut end) = f.end {
+                    end.subst(name, value);
+                }
+                subst_stmts(&mut f.statements, name, value);
+            },
+        }
+    }
+}
+
+fn subst_decl(decl: &mut VarDecl, name: &str, value: &Expr) {
+    let exprs = decl.size_expr.iter_mut()
+        .chain(decl.array.iter_mut())
+        .chain(decl.default.iter_mut().flat_map(|d| d.exprs_mut()))
+        .chain(decl.params.iter_mut());
+    for e in exprs {
+        e.subst(name, value);
+    }
+}
@@ -13,6 +13,7 @@ use syn::punctuated::Punctuated;
 mod kw {
     syn::custom_keyword!(rust_type);
     syn::custom_keyword!(optional);
+    syn::custom_keyword!(export);
     syn::custom_keyword!(extends);
     syn::custom_keyword!(template);
     syn::custom_keyword!(aligned);
@@ -22,9 +23,19 @@ mod kw {
     syn::custom_keyword!(uint);
     syn::custom_keyword!(bit);
     syn::custom_keyword!(class);
+    syn::custom_keyword!(fourcc);
+    syn::custom_keyword!(parent);
+    syn::custom_keyword!(handler);
 }
 
+mod builder;
+mod codegen;
 mod expr;
+mod index;
+mod inherit;
+mod model;
+mod read;
+mod write;
 
 use expr::{BinOp, Expr};
 
@@ -40,10 +51,21 @@ fn eat(input: ParseStream, end: Option<char>) -> Result<()> {
                 _ => rest = next,
             }
         }
-        return Ok(((), rest));
+        Ok(((), rest))
     })
 }
 
+// `{ default samplerate of media }`: nothing but words.
+fn is_prose(input: ParseStream) -> bool {
+    let mut cursor = input.cursor();
+    let mut words = 0;
+    while let Some((_, next)) = cursor.ident() {
+        cursor = next;
+        words += 1;
+    }
+    words > 1 && cursor.eof()
+}
+
 // aligned(8)
 #[derive(Debug)]
 struct Aligned {
@@ -67,13 +89,60 @@ impl Parse for Aligned {
 #[derive(Debug)]
 struct VarDecl {
     optional:   bool,
-    template:   bool,
+    is_const:   bool,
     iso_type:   String,
+    base_type:  String,
+    // where the type is, for error messages.
+    type_span:  Span,
     rust_type:  String,
+    signed:     bool,
     size:       u32,
+    size_expr:  Option<Expr>,
+    // arguments of a class, like `HandlerBox(handler_type)`.
+    params:     Vec<Expr>,
     array:      Option<Expr>,
     name:       String,
-    default:    Option<Expr>,
+    default:    Option<Init>,
+    // "# export: name", the value is known as `name` to the boxes inside.
+    export:     Vec<String>,
+}
+
+// The value after the `=` of a declaration.
+#[derive(Clone, Debug)]
+enum Init {
+    // = 0x0100, or = {if track_is_audio 0x0100 else 0}
+    Value(Expr),
+    // = { 0x00010000,0,0,0,0x00010000,0,0,0,0x40000000 }
+    List(Vec<Expr>),
+}
+
+impl Init {
+    // The expressions in the value.
+    fn exprs_mut(&mut self) -> std::slice::IterMut<'_, Expr> {
+        match self {
+            Init::Value(e) => std::slice::from_mut(e).iter_mut(),
+            Init::List(list) => list.iter_mut(),
+        }
+    }
+
+    fn exprs(&self) -> std::slice::Iter<'_, Expr> {
+        match self {
+            Init::Value(e) => std::slice::from_ref(e).iter(),
+            Init::List(list) => list.iter(),
+        }
+    }
+}
+
+// The value between the braces of `= { ... }`.
+impl Parse for Init {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let list: Punctuated<Expr, Token![,]> = input.parse_terminated(Expr::parse)?;
+        let mut list: Vec<Expr> = list.into_iter().collect();
+        if list.len() == 1 {
+            return Ok(Init::Value(list.remove(0)));
+        }
+        Ok(Init::List(list))
+    }
 }
 
 // [template] unsigned int(8)[16] [= default_value]
@@ -95,6 +164,7 @@ impl Parse for VarDecl {
             is_const = true;
         }
 
+        let type_span = input.span();
         let typ = if input.peek(kw::signed) || input.peek(kw::unsigned) {
             if input.peek(kw::signed) {
                 input.parse::<kw::signed>()?;
@@ -107,10 +177,13 @@ impl Parse for VarDecl {
             "int".to_string()
         } else {
             if input.peek(kw::int) {
+                // plain int(n) is signed.
                 input.parse::<kw::int>()?;
+                signed = true;
                 "int".to_string()
             } else if input.peek(kw::uint) {
                 input.parse::<kw::uint>()?;
+                unsigned = true;
                 "uint".to_string()
             } else if input.peek(kw::bit) {
                 input.parse::<kw::bit>()?;
@@ -119,9 +192,7 @@ impl Parse for VarDecl {
                 input.parse::<kw::class>()?;
                 "class".to_string()
             } else if input.peek(Ident) {
-                println!("XXX 1");
                 let name = input.parse::<Ident>()?;
-                println!("XXX 2");
                 name.to_string()
             } else {
                 return Err(input.error("expected `type`"));
@@ -145,17 +216,30 @@ impl Parse for VarDecl {
         iso_type.push_str(&typ);
         let rust_type;
         let mut size = 0;
+        let mut size_expr = None;
+        let mut params = Vec::new();
 
         if typ == "int" || typ == "uint" || typ == "bit" {
             // parentheses must follow.
             let content;
             parenthesized!(content in input);
-            let size_expr: Expr = content.parse()?;
-            size = size_expr.get_lit_int()?;
-            let span = size_expr.span();
+            let expr: Expr = content.parse()?;
+            let span = expr.span();
+
+            // The size is usually a number, but it can also be an
+            // expression like int(offset_size*8). Those can be at most
+            // 64 bits, which is what we size the rust type for.
+            match expr.get_lit_int() {
+                Ok(n) => size = n,
+                Err(_) => {
+                    size = 64;
+                    size_expr = Some(expr);
+                },
+            }
 
             // Translate to a rust type.
-            rust_type = match (typ.to_string().as_str(), signed, size) {
+            let typ = if typ == "uint" { "int" } else { typ.as_str() };
+            rust_type = match (typ, signed, size) {
                 ("int", true, 1..=8) => "i8",
                 ("int", true, 9..=16) => "i16",
                 ("int", true, 17..=32) => "i32",
@@ -164,7 +248,10 @@ impl Parse for VarDecl {
                 ("int", false, 9..=16) => "u16",
                 ("int", false, 17..=32) => "u32",
                 ("int", false, 33..=64) => "u64",
-                ("int", _, size) => return Err(Error::new(span, format!("unsupported int({})", size))),
+                ("int", _, size) => {
+                    let msg = format!("unsupported int({}), the size must be 1 to 64 bits", size);
+                    return Err(Error::new(span, msg));
+                },
                 ("bit", _, 1) => "bool",
                 ("bit", _, 2..=8) => "u8",
                 ("bit", _, 9..=16) => "u16",
@@ -172,18 +259,23 @@ impl Parse for VarDecl {
                 ("bit", _, 24) => "Flags",
                 ("bit", _, 25..=32) => "u32",
                 ("bit", _, 33..=64) => "u64",
-                ("bit", _, size) => return Err(Error::new(span, format!("unsupported bit({})", size))),
-                _ => return Err(Error::new(typ.span(), "expected `type`")),
+                ("bit", _, size) => {
+                    let msg = format!("unsupported bit({}), the size must be 1 to 64 bits", size);
+                    return Err(Error::new(span, msg));
+                },
+                _ => return Err(Error::new(type_span, "expected `type`")),
             }.to_string();
         } else {
-            // Other types here. XXX FIXME check against symbol table.
+            // Another class. If it does not exist, the compiler
+            // complains about the type of the field, at `type_span`.
             rust_type = typ.to_string();
 
-            // If it's a valid class (check!) then empty () may follow.
+            // If it's a valid class (check!) then its arguments may follow.
             if input.peek(token::Paren) {
                 let content;
                 parenthesized!(content in input);
-                eat(&content, None)?;
+                let p: Punctuated<Expr, Token![,]> = content.parse_terminated(Expr::parse)?;
+                params = p.into_iter().collect();
             }
         }
 
@@ -200,20 +292,31 @@ impl Parse for VarDecl {
                 array = Some(e);
             }
         }
-        println!("XXX 2.1");
 
         // This allows simple "ChannelLayout();" or "DownMixInstructions() []" with no name.
-        // XXX not actually correct, we allow stuff like template ChannelLayout(); etc.
         if input.peek(Token![;]) {
+            if rust_type != typ {
+                return Err(Error::new(type_span, format!("expected the name of the `{}`", iso_type)));
+            }
+            if template || is_const || signed || unsigned {
+                let msg = format!("`{}`: a class can not be template, const, signed or unsigned", iso_type);
+                return Err(Error::new(type_span, msg));
+            }
             return Ok(VarDecl{
                 optional: false,
-                template: false,
-                iso_type: iso_type.clone(),
-                rust_type: "".to_string(),
+                is_const: false,
+                iso_type,
+                base_type: typ,
+                type_span,
+                rust_type,
+                signed,
                 size,
+                size_expr,
+                params,
                 array,
-                name: iso_type,
+                name: String::new(),
                 default: None,
+                export: Vec::new(),
             });
         }
 
@@ -223,9 +326,15 @@ impl Parse for VarDecl {
             let _ = input.parse::<Token![type]>()?;
             "type".to_string()
         } else {
-            input.parse::<Ident>()?.to_string()
+            let name = input.parse::<Ident>()?;
+            // "loudness TrackLoudnessInfo[]" has the name and the type swapped.
+            let is_class = |s: &str| s.starts_with(char::is_uppercase);
+            if rust_type == typ && !is_class(&typ) && is_class(&name.to_string()) {
+                let msg = format!("unknown type `{}`, the type comes first: `{} {}`", typ, name, typ);
+                return Err(Error::new(type_span, msg));
+            }
+            name.to_string()
         };
-        println!("XXX 2.2");
 
         // *another* place for the array indicaton, sheesh
         if input.peek(token::Bracket) {
@@ -243,38 +352,39 @@ impl Parse for VarDecl {
         let mut default = None;
         if input.peek(Token![=]) {
             let _eq: Token![=] = input.parse()?;
-            // for now, we just ignore everything in a block.
             if input.peek(token::Brace) {
                 let content;
-                let span = braced!(content in input).span;
-                eprintln!("warning: ignoring default {{ ... }} block");
-                eprintln!(" --> [source file]:{}:{}", span.start().line, span.start().column);
-                let w = span.start().line.to_string().len();
-                eprintln!("{:.*} |", w, "           ");
-                eprintln!("{:<.*} |", w, span.start().line);
-                eprintln!("{:.*} |", w, "           ");
-                if span.start().line == 0 {
-                    eprintln!("(imprecise message because of current compiler / proc-macro restrictions)");
+                braced!(content in input);
+                if is_prose(&content) {
+                    // A default that the spec describes in words,
+                    // like `{ default samplerate of media}<<16`.
+                    // There is no default then; skip up to the ';'.
+                    eat(&content, None)?;
+                    eat(input, Some(';'))?;
+                } else {
+                    default = Some(content.parse::<Init>()?);
                 }
-                eat(&content, None)?;
-                // ... and the rest up to ';' as well.
-                eat(&input, Some(';'))?;
             } else {
                 let e: Expr = input.parse()?;
-                default = Some(e);
+                default = Some(Init::Value(e));
             }
         }
 
-        println!("XXX 3");
         Ok(VarDecl{
             optional: false,
-            template,
+            is_const,
             iso_type,
+            base_type: typ,
+            type_span,
             rust_type,
+            signed,
             size,
+            size_expr,
+            params,
             array,
             name,
             default,
+            export: Vec::new(),
         })
     }
 }
@@ -284,6 +394,63 @@ impl Parse for VarDecl {
 struct InformativeComment {
     optional:   bool,
     rust_type:  Option<String>,
+    export:     Vec<String>,
+    placement:  Placement,
+}
+
+// Where a box with a fourcc that is a variable can be, so that it can
+// be part of a `BoxNode`. Set after the arguments of `extends`:
+//
+//     class FreeSpaceBox extends Box(free_type) # fourcc: "free", "skip" {
+//     class TrackReferenceTypeBox(..) extends Box(reference_type) # parent: "tref" {
+//     class VisualSampleEntry(..) extends SampleEntry(codingname) # handler: "vide" {
+//
+// A class gets the ones of the class it extends, unless it has its own.
+#[derive(Clone, Debug, Default)]
+struct Placement {
+    // one of these fourccs.
+    fourcc:     Vec<String>,
+    // in a box with one of these fourccs.
+    parent:     Vec<String>,
+    // in a track with one of these handler types.
+    handler:    Vec<String>,
+}
+
+impl Placement {
+    fn is_empty(&self) -> bool {
+        self.fourcc.is_empty() && self.parent.is_empty() && self.handler.is_empty()
+    }
+
+    // Take the ones that are not set from the class that is extended.
+    fn inherit(&mut self, parent: Placement) {
+        if self.fourcc.is_empty() {
+            self.fourcc = parent.fourcc;
+        }
+        if self.parent.is_empty() {
+            self.parent = parent.parent;
+        }
+        if self.handler.is_empty() {
+            self.handler = parent.handler;
+        }
+    }
+}
+
+// A list of fourccs, like `"free", "skip"`.
+fn parse_fourccs(input: ParseStream) -> Result<Vec<String>> {
+    input.parse::<Token![:]>()?;
+    let mut list = Vec::new();
+    loop {
+        let lit: syn::LitStr = input.parse()?;
+        if lit.value().len() != 4 {
+            return Err(Error::new(lit.span(), "expected a fourcc"));
+        }
+        list.push(lit.value());
+        if !input.peek(Token![,]) {
+            break;
+        }
+        input.parse::<Token![,]>()?;
+    }
+    Ok(list)
 }
 
 // An informative comment.
@@ -292,6 +459,8 @@ impl Parse for InformativeComment {
         let mut res = InformativeComment {
             optional:   false,
             rust_type:  None,
+            export:     Vec::new(),
+            placement:  Placement::default(),
         };
         input.parse::<Token![#]>()?;
         loop {
@@ -308,6 +477,30 @@ impl Parse for InformativeComment {
                 }
                 let i: Ident = input.parse()?;
                 res.rust_type = Some(i.to_string());
+            } else if lookahead.peek(kw::export) {
+                // "export: name, name"
+                input.parse::<kw::export>()?;
+                input.parse::<Token![:]>()?;
+                loop {
+                    let i: Ident = input.parse()?;
+                    res.export.push(i.to_string());
+                    if !input.peek(Token![,]) {
+                        break;
+                    }
+                    input.parse::<Token![,]>()?;
+                }
+            } else if lookahead.peek(kw::fourcc) {
+                // "fourcc: "free", "skip""
+                input.parse::<kw::fourcc>()?;
+                res.placement.fourcc = parse_fourccs(input)?;
+            } else if lookahead.peek(kw::parent) {
+                // "parent: "stsd""
+                input.parse::<kw::parent>()?;
+                res.placement.parent = parse_fourccs(input)?;
+            } else if lookahead.peek(kw::handler) {
+                // "handler: "vide""
+                input.parse::<kw::handler>()?;
+                res.placement.handler = parse_fourccs(input)?;
             } else {
                 return Err(lookahead.error());
             }
@@ -324,7 +517,12 @@ impl Parse for InformativeComment {
 #[derive(Debug)]
 struct Extends {
     class:     String,
+    span:       Span,
     args:       Vec<ExtendsArg>,
+    // "# rust_type: Type" after the arguments is the type of the flags.
+    flags_type: Option<String>,
+    // "# fourcc: .." etc after the arguments, see `Placement`.
+    placement:  Placement,
 }
 
 // extends Box(arg, arg ..)
@@ -337,9 +535,25 @@ impl Parse for Extends {
         let content;
         parenthesized!(content in input);
         let args: Punctuated<ExtendsArg, Token![,]> = content.parse_terminated(ExtendsArg::parse)?;
+        let mut flags_type = None;
+        let mut placement = Placement::default();
+        if input.peek(Token![#]) {
+            let c: InformativeComment = input.parse()?;
+            if c.optional {
+                return Err(input.error("a class can not be optional"));
+            }
+            if !c.export.is_empty() {
+                return Err(input.error("the flags can not be exported"));
+            }
+            flags_type = c.rust_type;
+            placement = c.placement;
+        }
         Ok(Extends {
             class:  class.to_string(),
+            span:   class.span(),
             args:   args.into_iter().collect(),
+            flags_type,
+            placement,
         })
     }
 }
@@ -395,6 +609,11 @@ struct ClassHeader {
     name:       Ident,
     args:       Vec<VarDecl>,
     extends:    Option<Extends>,
+    placement:  Placement,
+    // arguments of the classes it extends that are passed a literal,
+    // like `grouping_type` "roll" for `VisualRollRecoveryEntry`. Set
+    // when the parents are merged into the class.
+    bound:      Vec<(String, Expr)>,
 }
 
 // Header of a class, i.e. the definitions before the body.
@@ -430,8 +649,18 @@ impl Parse for ClassHeader {
         }
 
         let mut extends: Option<Extends> = None;
+        let mut placement = Placement::default();
         if input.peek(kw::extends) {
-            extends = Some(input.parse()?);
+            let mut ext: Extends = input.parse()?;
+            placement = std::mem::take(&mut ext.placement);
+            extends = Some(ext);
+        } else if input.peek(Token![#]) {
+            // A class that extends nothing can be placed as well.
+            let c: InformativeComment = input.parse()?;
+            if c.optional || c.rust_type.is_some() || !c.export.is_empty() {
+                return Err(input.error("a class can only have a fourcc, parent or handler"));
+            }
+            placement = c.placement;
         }
 
         Ok(ClassHeader{
@@ -440,72 +669,11 @@ impl Parse for ClassHeader {
             name,
             args,
             extends,
+            placement,
+            bound: Vec::new(),
         })
     }
 }
-/*
-// Operators.
-#[derive(Debug)]
-enum Op {
-    EqEq,
-    Ne,
-    Lt,
-    Le,
-    And,
-}
-
-// Parse an operator. "==" or "!=", etc
-impl Parse for Op {
-    fn parse(input: ParseStream) -> Result<Self> {
-        let lookahead = input.lookahead1();
-        if lookahead.peek(Token![==]) {
-            let _: token::EqEq = input.parse()?;
-            Ok(Op::EqEq)
-        } else if lookahead.peek(Token![!=]) {
-            let _: token::Ne = input.parse()?;
-            Ok(Op::Ne)
-        } else if lookahead.peek(Token![<]) {
-            let _: token::Lt = input.parse()?;
-            Ok(Op::Lt)
-        } else if lookahead.peek(Token![<=]) {
-            let _: token::Le = input.parse()?;
-            Ok(Op::Le)
-        } else if lookahead.peek(Token![&]) {
-            let _: token::And = input.parse()?;
-            Ok(Op::And)
-        } else {
-            Err(lookahead.error())
-        }
-    }
-}
-
-// `if` expression. left == right, or left != right.
-#[derive(Debug)]
-struct IfExpr {
-    left:   Value,
-    right:  Value,
-    op:     BinOp,
-}
-
-// `if` expression. left == right, or left != right.
-impl Parse for IfExpr {
-    fn parse(input: ParseStream) -> Result<Self> {
-        let content;
-        parenthesized!(content in input);
-        let left: ValueToken = content.parse()?;
-        let op = BinOp::parse(&content)?;
-        let right: ValueToken = content.parse()?;
-        if !content.is_empty() {
-            return Err(input.error("expected `)`"));
-        }
-        Ok(IfExpr {
-            left: left.value,
-            op,
-            right: right.value,
-        })
-    }
-}
-*/
 
 // if (variable == some_value) { decls }
 #[derive(Debug)]
@@ -651,9 +819,15 @@ impl Parse for For {
             return Err(inner.error("expected `)`"));
         }
 
-        let inner;
-        braced!(inner in input);
-        let statements: Stmts = inner.parse()?;
+        // The body is a block, or a single statement.
+        let mut statements = if input.peek(token::Brace) {
+            let inner;
+            braced!(inner in input);
+            inner.parse()?
+        } else {
+            Stmts(vec![ input.parse()? ])
+        };
+        strip_index(&mut statements, &varname);
 
         Ok(For {
             start,
@@ -664,6 +838,54 @@ impl Parse for For {
     }
 }
 
+// do { ... } repeats until the end of the structure. It is a for
+// loop without an end. The counter is "j = 1;" right before the loop,
+// which Stmt drops, and "j++;" in the body.
+fn parse_do(input: ParseStream, mut counter: Option<Ident>) -> Result<For> {
+    input.parse::<Token![do]>()?;
+    let inner;
+    braced!(inner in input);
+    let mut statements = Vec::new();
+    while !inner.is_empty() {
+        if inner.peek(Ident) && inner.peek2(Token![+]) {
+            // j++;
+            let ident = inner.parse::<Ident>()?;
+            if let Some(ref c) = counter {
+                if *c != ident {
+                    return Err(Error::new(ident.span(), format!("expected `{}`", c)));
+                }
+            }
+            counter = Some(ident);
+            inner.parse::<Token![+]>()?;
+            inner.parse::<Token![+]>()?;
+            inner.parse::<Token![;]>()?;
+            continue;
+        }
+        statements.push(inner.parse()?);
+    }
+    let mut statements = Stmts(statements);
+    if let Some(counter) = counter {
+        strip_index(&mut statements, &counter);
+    }
+    Ok(For {
+        start: 1,
+        op: None,
+        end: None,
+        statements,
+    })
+}
+
+// In a loop, "int(16) x[i];" is one value for every iteration, not an array.
+fn strip_index(stmts: &mut Stmts, var: &Ident) {
+    for stmt in stmts.0.iter_mut() {
+        if let &mut Stmt::VarDecl(ref mut decl) = stmt {
+            if matches!(decl.array, Some(Expr::Variable(ref v)) if v == var) {
+                decl.array = None;
+            }
+        }
+    }
+}
+
 // member declaration, or an "if" expresion.
 #[derive(Debug)]
 enum Stmt {
@@ -689,6 +911,19 @@ impl Parse for Stmt {
             input.parse::<Token![;]>()?;
         }
 
+        // drop "j = 1;", the counter of the do loop that must follow.
+        let mut counter = None;
+        if input.peek(Ident) && input.peek2(Token![=]) {
+            let ident = input.parse::<Ident>()?;
+            input.parse::<Token![=]>()?;
+            input.parse::<Expr>()?;
+            input.parse::<Token![;]>()?;
+            if !input.peek(Token![do]) {
+                return Err(Error::new(ident.span(), format!("`{}` is set, but no do loop follows", ident)));
+            }
+            counter = Some(ident);
+        }
+
         let lookahead = input.lookahead1();
         if lookahead.peek(Token![if]) {
             let res: IfElse = input.parse()?;
@@ -696,6 +931,23 @@ impl Parse for Stmt {
         } else if lookahead.peek(Token![for]) {
             let res: For = input.parse()?;
             Ok(Stmt::For(res))
+        } else if lookahead.peek(Token![do]) {
+            Ok(Stmt::For(parse_do(input, counter)?))
+        } else if lookahead.peek(token::Brace) {
+            // { decls }[ count ] is an array of anonymous structs,
+            // which is really just another way to write a for loop.
+            let inner;
+            braced!(inner in input);
+            let statements: Stmts = inner.parse()?;
+            let content;
+            let span = bracketed!(content in input).span;
+            let end: Expr = content.parse()?;
+            Ok(Stmt::For(For {
+                start: 0,
+                op: Some(BinOp::Lt(token::Lt(span))),
+                end: Some(end),
+                statements,
+            }))
         } else if lookahead.peek(Ident) || lookahead.peek(token::Const) {
             let mut decl: VarDecl = input.parse()?;
             input.parse::<Token![;]>()?;
@@ -709,9 +961,13 @@ impl Parse for Stmt {
                         decl.iso_type = format!("optional {}", decl.iso_type);
                     }
                 }
+                if !c.placement.is_empty() {
+                    return Err(input.error("only a class can have a fourcc, parent or handler"));
+                }
                 if let Some(rt) = c.rust_type {
                     decl.rust_type = rt;
                 }
+                decl.export = c.export;
             }
 
             Ok(Stmt::VarDecl(decl))
@@ -774,8 +1030,20 @@ impl Parse for Class {
 
 #[proc_macro]
 pub fn def_box(input: TokenStream) -> TokenStream {
-    let class = parse_macro_input!(input as Class);
-    println!("item: \"{:#?}\"", class);
-    TokenStream::new()
+    let input = parse_macro_input!(input as inherit::Input);
+    match inherit::expand(input) {
+        Ok(out) => out.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
 }
 
+// The list of boxes for `BoxNode`, see index.rs.
+#[doc(hidden)]
+#[proc_macro]
+pub fn box_index(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as index::Input);
+    match index::expand(input) {
+        Ok(out) => out.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,989 @@
+// Lowering of a parsed `Class` into the Rust data model.
+//
+// The ISO syntax declares members in the order in which they are read,
+// possibly several times (in different branches of an `if`), and with
+// loops that repeat a group of members. Here we work out what the
+// resulting Rust structs look like:
+//
+// - members declared more than once are merged into one field, wide
+//   enough for all declarations.
+// - members that are only present conditionally become an `Option`.
+// - a loop becomes a `Vec` of the member in its body, or if the body
+//   has more than one member, a `Vec` of a separate "entry" struct.
+// - `const` and reserved members are not part of the struct at all.
+//
+use std::collections::{HashMap, HashSet};
+
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, format_ident};
+use syn::{Ident, Result, Error};
+
+use crate::{Class, ExtendsArg, Init, Placement, Stmt, Stmts, VarDecl, IfElse, For, BinOp};
+use crate::expr::Expr;
+
+// Names of members that are not part of the struct, since they
+// are always zero (or their default) in a well-formed file.
+const HIDDEN: &[&str] = &[ "reserved", "pre_defined", "pad" ];
+
+// Variables that are not declared in the class, but that are known
+// from other boxes (`BoxReader::lookup`) when the class is read.
+const EXTERNAL: &[&str] = &[
+    // HandlerBox
+    "handler_type",
+    // TrackHeaderBox, the default volume.
+    "track_is_audio",
+    // ChannelLayout, from the AudioSampleEntry.
+    "channelCount",
+    // DownMixInstructions, from the AudioSampleEntry.
+    "baseChannelCount",
+];
+
+// Loop counts that are in another box that is not the parent, like
+// `sample_count` of `sdtp`, which is in `stsz`, `stz2` or `trun`. If
+// the class does not declare them itself, these loops run to the end
+// of the box.
+const TO_END: &[&str] = &[ "sample_count" ];
+
+// Constants that the spec uses in expressions.
+pub const CONSTANTS: &[(&str, i64)] = &[
+    // ChannelLayout
+    ("channelStructured", 1),
+    ("objectStructured", 2),
+];
+
+// The type of a field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    // int(n), uint(n), bit(n).
+    Int { bits: u32, signed: bool },
+    // bit(1).
+    Bool,
+    // bit(24).
+    Flags,
+    // A rust type, set with "# rust_type: Type".
+    Rust(String),
+    // string.
+    String,
+    // string[length].
+    FixedString,
+    // Another class.
+    Class(Ident),
+    // Box: any box.
+    AnyBox,
+    // A child box of a container.
+    Node,
+    // How the size in the header of a box is written.
+    SizeForm,
+    // Array with a fixed size.
+    Array(Box<Type>, u32),
+    // Array with a size that is known when parsing.
+    Vec(Box<Type>),
+    // The body of a loop. Resolves to Vec<T> or Vec<Entry> when done.
+    Loop(usize),
+    // Struct for the body of a loop.
+    Entry(String),
+}
+
+impl Type {
+    // Type of a declaration, without the array part.
+    pub fn from_decl(decl: &VarDecl) -> Type {
+        match decl.base_type.as_str() {
+            "int" | "uint" | "bit" => {
+                match decl.rust_type.as_str() {
+                    "bool" => Type::Bool,
+                    "Flags" => Type::Flags,
+                    "u8" | "u16" | "u32" | "u64" |
+                    "i8" | "i16" | "i32" | "i64" => Type::Int{ bits: decl.size, signed: decl.signed },
+                    other => Type::Rust(other.to_string()),
+                }
+            },
+            "string" => Type::String,
+            "Box" => Type::AnyBox,
+            class => {
+                if decl.rust_type != class {
+                    Type::Rust(decl.rust_type.clone())
+                } else {
+                    Type::Class(Ident::new(class, decl.type_span))
+                }
+            },
+        }
+    }
+
+    // Merge two declarations of the same member.
+    pub fn merge(&self, other: &Type) -> Option<Type> {
+        if self == other {
+            return Some(self.clone());
+        }
+        let int = |t: &Type| match *t {
+            Type::Int{ bits, signed } => Some((bits, signed)),
+            Type::Bool => Some((1, false)),
+            _ => None,
+        };
+        match (self, other) {
+            (&Type::Array(ref a, n1), &Type::Array(ref b, n2)) if n1 == n2 => {
+                Some(Type::Array(Box::new(a.merge(b)?), n1))
+            },
+            (Type::Vec(a), Type::Vec(b)) => Some(Type::Vec(Box::new(a.merge(b)?))),
+            (a, b) => {
+                let (bits1, signed1) = int(a)?;
+                let (bits2, signed2) = int(b)?;
+                // If one is signed and the other not, the signed
+                // type needs an extra bit to hold the unsigned one.
+                let (bits, signed) = match (signed1, signed2) {
+                    (false, false) => (std::cmp::max(bits1, bits2), false),
+                    (true, true) => (std::cmp::max(bits1, bits2), true),
+                    (true, false) => (std::cmp::max(bits1, bits2 + 1), true),
+                    (false, true) => (std::cmp::max(bits1 + 1, bits2), true),
+                };
+                Some(Type::Int{ bits: std::cmp::min(bits, 64), signed })
+            },
+        }
+    }
+
+    // The rust type.
+    pub fn tokens(&self) -> TokenStream {
+        match self {
+            &Type::Int{ bits, signed } => {
+                let bits: u32 = match bits {
+                    0..=8 => 8,
+                    9..=16 => 16,
+                    17..=32 => 32,
+                    _ => 64,
+                };
+                let name = format_ident!("{}{}", if signed { "i" } else { "u" }, bits);
+                quote!(#name)
+            },
+            &Type::Bool => quote!(bool),
+            &Type::Flags => quote!(::iso_bmff::Flags),
+            Type::Rust(name) => {
+                let name = Ident::new(name, Span::call_site());
+                quote!(#name)
+            },
+            &Type::String => quote!(::iso_bmff::BoxString),
+            &Type::FixedString => quote!(::iso_bmff::FixedString),
+            Type::Class(name) => quote!(#name),
+            Type::Entry(name) => {
+                let name = Ident::new(name, Span::call_site());
+                quote!(#name)
+            },
+            &Type::AnyBox => quote!(::iso_bmff::RawBox),
+            &Type::Node => quote!(::iso_bmff::BoxNode),
+            &Type::SizeForm => quote!(::iso_bmff::SizeForm),
+            &Type::Array(ref t, size) => {
+                let t = t.tokens();
+                let size = size as usize;
+                quote!([#t; #size])
+            },
+            Type::Vec(t) => {
+                let t = t.tokens();
+                quote!(Vec<#t>)
+            },
+            &Type::Loop(_) => unreachable!(),
+        }
+    }
+}
+
+// A field of a struct.
+#[derive(Clone, Debug)]
+pub struct Field {
+    // name in the ISO syntax.
+    pub name:       String,
+    // name in the rust struct.
+    pub ident:      Ident,
+    pub ty:         Type,
+    // only present conditionally.
+    pub optional:   bool,
+    // the number of entries in a loop, computed when writing
+    // from the length of a Vec, plus an offset.
+    pub derived:    Option<(Ident, i64)>,
+    // the default value, from `= value`.
+    pub default:    Option<Init>,
+    // present if this bit of the flags is set, as in `if (tf_flags & 0x01)`.
+    pub flag:       Option<i64>,
+    // `# optional` at the end of a box: present if there is data left.
+    pub trailing:   bool,
+    // `# export: name`, known by that name to the boxes inside.
+    pub export:     Vec<String>,
+}
+
+impl Field {
+    fn new(name: &str, ty: Type) -> Field {
+        Field {
+            name:       name.to_string(),
+            ident:      Ident::new(&rust_name(name), Span::call_site()),
+            ty,
+            optional:   false,
+            derived:    None,
+            default:    None,
+            flag:       None,
+            trailing:   false,
+            export:     Vec::new(),
+        }
+    }
+
+    // The type of this field in the struct.
+    pub fn tokens(&self) -> TokenStream {
+        let ty = self.ty.tokens();
+        if self.optional {
+            quote!(Option<#ty>)
+        } else {
+            ty
+        }
+    }
+}
+
+// A run of fields: the class itself, or the body of a loop.
+#[derive(Debug, Default)]
+pub struct Level {
+    // Name of the entry struct, or the plural name of the field.
+    pub name:       String,
+    pub plural:     String,
+    pub fields:     Vec<Field>,
+    // There is no entry struct, the loop body is just one field.
+    pub inline:     bool,
+}
+
+// A variable, as the level it is in and its name.
+type VarRef = (usize, String);
+
+// Where a variable is used.
+#[derive(Debug)]
+enum Use {
+    // As the number of entries in a loop or array.
+    Count {
+        target:     VarRef,
+        offset:     i64,
+        clean:      bool,
+    },
+    // Anywhere else.
+    Other,
+}
+
+// How the header of a box is defined.
+#[derive(Debug)]
+pub enum Value {
+    // Not present.
+    None,
+    // A literal value.
+    Lit(Expr),
+    // A field.
+    Var(String),
+}
+
+// The class we are extending.
+#[derive(Debug, PartialEq)]
+pub enum Root {
+    Box,
+    FullBox,
+    Class(String),
+    None,
+}
+
+// A lowered class.
+#[derive(Debug)]
+pub struct Model {
+    pub name:       Ident,
+    pub root:       Root,
+    pub fourcc:     Value,
+    pub version:    Value,
+    pub flags:      Value,
+    // only the base of other classes.
+    pub is_abstract: bool,
+    // aligned(n): the class starts and ends at a multiple of n bits.
+    pub aligned:    Option<u32>,
+    // a box without fields, that contains other boxes.
+    pub container:  bool,
+    // the extended type of a `uuid` box.
+    pub usertype:   Option<[u8; 16]>,
+    // where the box can be, if its fourcc is a variable.
+    pub placement:  Placement,
+    // arguments of the parents that are passed a literal.
+    pub bound:      Vec<(String, Expr)>,
+    // the arguments of the class, like `format` in `SampleEntry(format)`.
+    pub args:       Vec<String>,
+    // the versions that the layout depends on, lowest first. Empty if
+    // the version is not a field, or if the layout does not depend on it.
+    pub versions:   Vec<i64>,
+    // the number of statements at the end of a box that are child boxes,
+    // like `BitRateBox ();` in a sample entry. They come in any order.
+    pub slots:      usize,
+    // the fields of those child boxes.
+    pub slot_fields: Vec<String>,
+    // level 0 is the class itself, the others are loop bodies.
+    pub levels:     Vec<Level>,
+    // the level of the body of every loop.
+    pub loops:      HashMap<*const For, usize>,
+    // the loops that run to the end of the box, see `TO_END`.
+    pub to_end:     HashSet<*const For>,
+    uses:           Vec<(VarRef, Use)>,
+    visible:        Vec<(String, usize)>,
+    conditions:     Vec<bool>,
+}
+
+impl Model {
+    pub fn lower(class: &Class) -> Result<Model> {
+        let head = &class.head;
+        let mut model = Model {
+            name:       head.name.clone(),
+            root:       Root::None,
+            fourcc:     Value::None,
+            version:    Value::None,
+            flags:      Value::None,
+            is_abstract: head.is_abstract,
+            aligned:    None,
+            container:  false,
+            usertype:   None,
+            placement:  head.placement.clone(),
+            bound:      head.bound.clone(),
+            args:       Vec::new(),
+            versions:   Vec::new(),
+            slots:      0,
+            slot_fields: Vec::new(),
+            levels:     vec![ Level::default() ],
+            loops:      HashMap::new(),
+            to_end:     HashSet::new(),
+            uses:       Vec::new(),
+            visible:    Vec::new(),
+            conditions: Vec::new(),
+        };
+        let mut fields = Vec::new();
+
+        if let Some(ref aligned) = head.aligned {
+            if aligned.value == 0 || aligned.value > 64 || !aligned.value.is_power_of_two() {
+                return Err(Error::new(aligned.span, format!("unsupported aligned({})", aligned.value)));
+            }
+            model.aligned = Some(aligned.value);
+        }
+
+        // The arguments of the class are fields.
+        for arg in &head.args {
+            model.add_decl(&mut fields, arg, 0)?;
+            model.args.push(decl_name(arg));
+        }
+
+        // The arguments of `Box` and `FullBox` are the header.
+        if let Some(ref ext) = head.extends {
+            model.root = match ext.class.as_str() {
+                "Box" => Root::Box,
+                "FullBox" => Root::FullBox,
+                other => Root::Class(other.to_string()),
+            };
+            if model.root == Root::Box || model.root == Root::FullBox {
+                // `extended_type = "uuid"` is the usertype of a `uuid` box.
+                let is_ext = |arg: &&ExtendsArg| arg.varname.as_ref().map(|v| v == "extended_type").unwrap_or(false);
+                if let Some(arg) = ext.args.iter().find(is_ext) {
+                    model.usertype = match arg.value {
+                        Some(Expr::LitStr(ref s)) => match parse_uuid(&s.value()) {
+                            Some(u) => Some(u),
+                            None => return Err(Error::new(s.span(), "invalid UUID")),
+                        },
+                        _ => return Err(Error::new(head.name.span(), "extended_type must be a UUID string")),
+                    };
+                }
+                for (idx, arg) in ext.args.iter().filter(|a| !is_ext(a)).enumerate() {
+                    let ty = match idx {
+                        0 => Type::Int{ bits: 32, signed: false },
+                        1 if model.root == Root::FullBox => Type::Int{ bits: 8, signed: false },
+                        2 if model.root == Root::FullBox => match ext.flags_type {
+                            Some(ref t) => Type::Rust(t.clone()),
+                            None => Type::Flags,
+                        },
+                        _ => return Err(Error::new(head.name.span(), "too many arguments")),
+                    };
+                    let value = match (&arg.varname, &arg.value) {
+                        (_, &Some(Expr::Variable(ref v))) => {
+                            model.add_field(&mut fields, Field::new(&v.to_string(), ty), 0);
+                            Value::Var(v.to_string())
+                        },
+                        (_, Some(e)) => Value::Lit(e.clone()),
+                        (Some(v), None) => {
+                            model.add_field(&mut fields, Field::new(&v.to_string(), ty), 0);
+                            Value::Var(v.to_string())
+                        },
+                        (None, None) => unreachable!(),
+                    };
+                    match idx {
+                        0 => model.fourcc = value,
+                        1 => model.version = value,
+                        _ => model.flags = value,
+                    }
+                }
+            }
+        }
+
+        // And the body.
+        let fixed = fields.len();
+        model.lower_stmts(&class.body.statements, &mut fields, 0)?;
+
+        // A box with an empty body, like `MovieBox`, contains other boxes.
+        if let (&Root::Box, &Value::Lit(_)) = (&model.root, &model.fourcc) {
+            if class.body.statements.0.is_empty() {
+                model.container = true;
+                fields.push(Field::new("boxes", Type::Vec(Box::new(Type::Node))));
+            }
+        }
+        // Boxes at the end of a box are child boxes. Any number of
+        // `Box` there are all the other boxes, kept as a `BoxNode`.
+        if model.root == Root::Box || model.root == Root::FullBox {
+            model.slots = count_slots(&class.body.statements.0);
+            let any = Type::Vec(Box::new(Type::AnyBox));
+            for decl in model.slot_decls(&class.body.statements) {
+                let name = decl_name(decl);
+                if let Some(f) = fields.iter_mut().find(|f| f.name == name && f.ty == any) {
+                    f.ty = Type::Vec(Box::new(Type::Node));
+                }
+                model.slot_fields.push(name);
+            }
+            // The order in which the child boxes were read, as the index
+            // of their declaration, if it is not the order of the
+            // declarations. They are written back in that order.
+            if model.slots > 0 {
+                fields.push(Field::new("child_order", Type::Vec(Box::new(Type::Int{ bits: 8, signed: false }))));
+            }
+        }
+        // Keep the form of the size, so that a box with a largesize
+        // or a size of 0 is written back the same.
+        if model.root == Root::Box || model.root == Root::FullBox {
+            fields.push(Field::new("size_form", Type::SizeForm));
+        }
+        model.levels[0].fields = fields;
+        model.levels[0].name = model.name.to_string();
+        model.finish(fixed);
+
+        if let Some(version) = model.version_field() {
+            let mut versions = vec![ 0 ];
+            compared_values(&class.body.statements, &version.name, &mut versions);
+            versions.retain(|&v| (0..=255).contains(&v));
+            versions.sort_unstable();
+            versions.dedup();
+            if versions.len() > 1 {
+                model.versions = versions;
+            }
+        }
+        Ok(model)
+    }
+
+    // The declarations of the child boxes at the end of the class.
+    pub fn slot_decls<'a>(&self, stmts: &'a Stmts) -> impl Iterator<Item = &'a VarDecl> {
+        stmts.0[stmts.0.len() - self.slots ..].iter().filter_map(|s| match s {
+            Stmt::VarDecl(decl) => Some(decl),
+            _ => None,
+        })
+    }
+
+    // The field that holds the version of a FullBox.
+    pub fn version_field(&self) -> Option<&Field> {
+        match self.version {
+            Value::Var(ref v) if self.root == Root::FullBox => self.field(&[0], v),
+            _ => None,
+        }
+    }
+
+    // The end of a loop, `None` if it runs to the end of the box.
+    pub fn loop_end<'a>(&self, f: &'a For) -> Option<&'a Expr> {
+        if self.to_end.contains(&(f as *const For)) {
+            None
+        } else {
+            f.end.as_ref()
+        }
+    }
+
+    // Look up the level where a variable was declared.
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.visible.iter().rev().find(|v| v.0 == name).map(|v| v.1)
+    }
+
+    // Check that a variable that is not a field is known.
+    fn check_var(&self, v: &Ident) -> Result<()> {
+        let name = v.to_string();
+        let header = self.root == Root::FullBox && (name == "version" || name == "flags");
+        let constant = CONSTANTS.iter().any(|c| c.0 == name);
+        if header || constant || EXTERNAL.contains(&name.as_str()) {
+            return Ok(());
+        }
+        Err(Error::new(v.span(), format!("cannot find variable `{}` in class `{}`", name, self.name)))
+    }
+
+    // Record the variables used in an expression.
+    fn add_uses(&mut self, expr: &Expr) -> Result<()> {
+        let mut vars = Vec::new();
+        expr.variables(&mut vars);
+        for v in vars {
+            match self.resolve(&v.to_string()) {
+                Some(level) => self.uses.push(((level, v.to_string()), Use::Other)),
+                None => self.check_var(&v)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Record the use of an expression as the count of a loop or array.
+    fn add_count(&mut self, expr: &Expr, target: VarRef, offset: i64) -> Result<()> {
+        match expr.strip_parens() {
+            Expr::Variable(v) => {
+                match self.resolve(&v.to_string()) {
+                    Some(level) => {
+                        let clean = self.conditions.iter().all(|c| *c);
+                        self.uses.push(((level, v.to_string()), Use::Count{ target, offset, clean }));
+                    },
+                    None => self.check_var(v)?,
+                }
+                Ok(())
+            },
+            other => self.add_uses(other),
+        }
+    }
+
+    // Add a field to a list of fields, merging it with an existing one.
+    fn add_field(&mut self, fields: &mut Vec<Field>, field: Field, level: usize) -> Option<()> {
+        self.visible.push((field.name.clone(), level));
+        if let Some(f) = fields.iter_mut().find(|f| f.name == field.name) {
+            f.ty = f.ty.merge(&field.ty)?;
+            f.optional = f.optional && field.optional;
+            if f.default.is_none() {
+                f.default = field.default;
+            }
+        } else {
+            fields.push(field);
+        }
+        Some(())
+    }
+
+    // Add a declaration.
+    fn add_decl(&mut self, fields: &mut Vec<Field>, decl: &VarDecl, level: usize) -> Result<()> {
+        if let Some(ref e) = decl.size_expr {
+            self.add_uses(e)?;
+        }
+        for e in decl.default.iter().flat_map(|d| d.exprs()) {
+            self.add_uses(e)?;
+        }
+        for e in &decl.params {
+            self.add_uses(e)?;
+        }
+        if is_hidden(decl) {
+            return Ok(());
+        }
+
+        let name = decl_name(decl);
+        let mut ty = Type::from_decl(decl);
+        if let Some(ref array) = decl.array {
+            if ty == Type::String {
+                // string[32] is a fixed size string.
+                ty = Type::FixedString;
+            } else if let Ok(size) = array.get_lit_int() {
+                if size == 0 {
+                    ty = Type::Vec(Box::new(ty));
+                } else {
+                    ty = Type::Array(Box::new(ty), size);
+                }
+            } else {
+                self.add_count(array, (level, name.clone()), 0)?;
+                ty = Type::Vec(Box::new(ty));
+            }
+        } else if decl.name.is_empty() && ty == Type::AnyBox {
+            // "Box ();" means any number of boxes.
+            ty = Type::Vec(Box::new(ty));
+        }
+
+        let mut field = Field::new(&name, ty);
+        field.default = decl.default.clone();
+        field.optional = decl.optional;
+        field.trailing = decl.optional;
+        field.export = decl.export.clone();
+        match self.add_field(fields, field, level) {
+            Some(()) => Ok(()),
+            None => Err(Error::new(self.name.span(), format!("{}: conflicting types", name))),
+        }
+    }
+
+    // Lower a run of statements into a list of fields.
+    fn lower_stmts(&mut self, stmts: &Stmts, fields: &mut Vec<Field>, level: usize) -> Result<()> {
+        for stmt in &stmts.0 {
+            match stmt {
+                Stmt::VarDecl(decl) => self.add_decl(fields, decl, level)?,
+                Stmt::If(ifelse) => self.lower_if(ifelse, fields, level)?,
+                Stmt::For(f) => self.lower_for(f, fields, level)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Lower if / else if / else.
+    fn lower_if(&mut self, ifelse: &IfElse, fields: &mut Vec<Field>, level: usize) -> Result<()> {
+        let mut exprs = vec![ &ifelse.ifexpr ];
+        let mut branches = vec![ &ifelse.if_true ];
+        for i in &ifelse.if_else {
+            exprs.push(&i.ifexpr);
+            branches.push(&i.if_true);
+        }
+        let exhaustive = !ifelse.if_false.0.is_empty();
+        if exhaustive {
+            branches.push(&ifelse.if_false);
+        }
+
+        // Conditions that only test the header (version and flags)
+        // do not make the count of a loop ambiguous.
+        let mut clean = true;
+        for e in &exprs {
+            self.add_uses(e)?;
+            let mut vars = Vec::new();
+            e.variables(&mut vars);
+            clean &= vars.iter().all(|v| self.is_header(&v.to_string()));
+        }
+
+        // Lower every branch on its own.
+        self.conditions.push(clean);
+        let mut lowered = Vec::new();
+        for b in &branches {
+            let mut bfields = Vec::new();
+            self.lower_stmts(b, &mut bfields, level)?;
+            lowered.push(bfields);
+        }
+        self.conditions.pop();
+
+        // if (flags & 0x01) { ... } without an else.
+        let flag = match ifelse.ifexpr.strip_parens() {
+            &Expr::Binary{ ref left, op: BinOp::And(_), ref right } if !exhaustive && ifelse.if_else.is_empty() => {
+                match (left.strip_parens(), right.strip_parens()) {
+                    (&Expr::Variable(ref v), &Expr::LitInt(ref lit)) |
+                    (&Expr::LitInt(ref lit), &Expr::Variable(ref v)) if matches!(self.flags, Value::Var(ref f) if v == f) => {
+                        lit.base10_parse::<i64>().ok()
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+
+        // A field that is present in all branches is not optional.
+        let in_all: Vec<String> = lowered[0].iter()
+            .filter(|f| lowered.iter().all(|b| b.iter().any(|bf| bf.name == f.name)))
+            .map(|f| f.name.clone())
+            .collect();
+        for bfields in lowered {
+            for mut f in bfields {
+                if !exhaustive || !in_all.contains(&f.name) {
+                    f.optional = true;
+                }
+                if f.flag.is_none() {
+                    f.flag = flag;
+                }
+                self.merge_field(fields, f, level)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Lower a loop. The body ends up in a level of its own.
+    fn lower_for(&mut self, f: &For, fields: &mut Vec<Field>, level: usize) -> Result<()> {
+        let (plural, singular) = match f.end {
+            Some(ref e) => count_names(e),
+            None => count_names(&Expr::new_lit_int(0, Span::call_site())),
+        };
+
+        // Loops over the same thing (in different branches of an if)
+        // share the entry struct.
+        let name = format!("{}{}", self.name, camel_case(&singular));
+        let idx = match self.levels.iter().position(|l| l.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.levels.push(Level {
+                    name,
+                    plural: plural.clone(),
+                    ..Level::default()
+                });
+                self.levels.len() - 1
+            }
+        };
+        self.loops.insert(f as *const For, idx);
+        if let Some(Expr::Variable(ref v)) = f.end {
+            let name = v.to_string();
+            if TO_END.contains(&name.as_str()) && self.resolve(&name).is_none() {
+                self.to_end.insert(f as *const For);
+            }
+        }
+        if let Some(e) = self.loop_end(f) {
+            // for (i = 1; i <= count; i++) has `count` entries.
+            let offset = match f.op {
+                Some(BinOp::Le(_)) => f.start as i64 - 1,
+                _ => f.start as i64,
+            };
+            self.add_count(e, (level, self.levels[idx].plural.clone()), offset)?;
+        }
+
+        let visible = self.visible.len();
+        let conditions = std::mem::take(&mut self.conditions);
+        let mut body = std::mem::take(&mut self.levels[idx].fields);
+        self.lower_stmts(&f.statements, &mut body, idx)?;
+        self.levels[idx].fields = body;
+        self.conditions = conditions;
+        self.visible.truncate(visible);
+
+        let plural = self.levels[idx].plural.clone();
+        self.merge_field(fields, Field::new(&plural, Type::Loop(idx)), level)
+    }
+
+    fn merge_field(&mut self, fields: &mut Vec<Field>, field: Field, level: usize) -> Result<()> {
+        let name = field.name.clone();
+        match self.add_field(fields, field, level) {
+            Some(()) => Ok(()),
+            None => Err(Error::new(self.name.span(), format!("`{}` declared with conflicting types", name))),
+        }
+    }
+
+    // Is this variable one of the header fields.
+    fn is_header(&self, name: &str) -> bool {
+        match (&self.version, &self.flags) {
+            (Value::Var(v), _) if v == name => true,
+            (_, Value::Var(f)) if f == name => true,
+            _ => false,
+        }
+    }
+
+    // Resolve loops into Vecs, and find out which fields are derived.
+    fn finish(&mut self, fixed: usize) {
+        for level in self.levels.iter_mut().skip(1) {
+            level.inline = level.fields.len() == 1 && !level.fields[0].optional;
+        }
+
+        // Replace Type::Loop by the real type. Nested loops have a
+        // higher index, so go backwards to do the innermost first.
+        let mut renamed = HashMap::new();
+        for l in (0 .. self.levels.len()).rev() {
+            for f in 0 .. self.levels[l].fields.len() {
+                let idx = match self.levels[l].fields[f].ty {
+                    Type::Loop(idx) => idx,
+                    Type::Vec(_) => {
+                        self.levels[l].fields[f].optional = false;
+                        continue;
+                    },
+                    _ => continue,
+                };
+                let field = if self.levels[idx].inline {
+                    let inner = &self.levels[idx].fields[0];
+                    let mut field = Field::new(&inner.name, Type::Vec(Box::new(inner.ty.clone())));
+                    field.ident = inner.ident.clone();
+                    field
+                } else {
+                    let level = &self.levels[idx];
+                    Field::new(&level.plural, Type::Vec(Box::new(Type::Entry(level.name.clone()))))
+                };
+                renamed.insert((l, self.levels[l].fields[f].name.clone()), field.name.clone());
+                self.levels[l].fields[f] = field;
+            }
+        }
+
+        // A field that is only used as the count of one loop or array,
+        // can be derived from the length of the Vec when writing.
+        let mut derived: HashMap<VarRef, Option<(VarRef, i64)>> = HashMap::new();
+        for (var, u) in &self.uses {
+            let entry = derived.entry(var.clone());
+            let target = match u {
+                &Use::Count{ ref target, offset, clean: true } if target.0 == var.0 => {
+                    let target = renamed.get(target).map(|n| (target.0, n.clone())).unwrap_or(target.clone());
+                    Some((target, offset))
+                },
+                _ => None,
+            };
+            let e = entry.or_insert(target.clone());
+            if *e != target {
+                *e = None;
+            }
+        }
+        for ((level, name), target) in derived {
+            let ((_, target), offset) = match target {
+                Some(target) => target,
+                None => continue,
+            };
+            let skip = if level == 0 { fixed } else { 0 };
+            let fields = &mut self.levels[level].fields;
+            let target = match fields.iter().find(|f| f.name == target) {
+                Some(f) => f.ident.clone(),
+                None => continue,
+            };
+            if let Some(f) = fields.iter_mut().skip(skip).find(|f| f.name == name) {
+                if let Type::Int{..} = f.ty {
+                    f.derived = Some((target, offset));
+                }
+            }
+        }
+    }
+
+    // The fields of the struct for a level.
+    pub fn struct_fields(&self, level: usize) -> impl Iterator<Item=&Field> {
+        self.levels[level].fields.iter().filter(|f| f.derived.is_none())
+    }
+
+    // Find the field for a variable, looking through the levels
+    // in `scope` from the innermost outwards.
+    pub fn field(&self, scope: &[usize], name: &str) -> Option<&Field> {
+        scope.iter().rev().find_map(|&l| self.levels[l].fields.iter().find(|f| f.name == name))
+    }
+
+    // The field in the enclosing level that holds the entries of a loop.
+    pub fn loop_field(&self, idx: usize) -> Ident {
+        let level = &self.levels[idx];
+        if level.inline {
+            level.fields[0].ident.clone()
+        } else {
+            Ident::new(&rust_name(&level.plural), Span::call_site())
+        }
+    }
+
+    // The entry structs.
+    pub fn entries(&self) -> impl Iterator<Item=(usize, &Level)> {
+        self.levels.iter().enumerate().skip(1).filter(|l| !l.1.inline)
+    }
+}
+
+// The values that a variable is compared with in the conditions.
+fn compared_values(stmts: &Stmts, name: &str, values: &mut Vec<i64>) {
+    for stmt in &stmts.0 {
+        match stmt {
+            &Stmt::VarDecl(_) => {},
+            Stmt::If(ifelse) => {
+                ifelse.ifexpr.compared_values(name, values);
+                compared_values(&ifelse.if_true, name, values);
+                for i in &ifelse.if_else {
+                    i.ifexpr.compared_values(name, values);
+                    compared_values(&i.if_true, name, values);
+                }
+                compared_values(&ifelse.if_false, name, values);
+            },
+            Stmt::For(f) => compared_values(&f.statements, name, values),
+        }
+    }
+}
+
+// "6d1d9b05-42d5-44e6-80e2-141daff757b2" -> 16 bytes.
+fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let hex: Vec<u8> = s.bytes().filter(|&b| b != b'-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut uuid = [0u8; 16];
+    for (i, b) in uuid.iter_mut().enumerate() {
+        let s = std::str::from_utf8(&hex[i * 2 .. i * 2 + 2]).ok()?;
+        *b = u8::from_str_radix(s, 16).ok()?;
+    }
+    Some(uuid)
+}
+
+// Is a declaration left out of the struct.
+pub fn is_hidden(decl: &VarDecl) -> bool {
+    decl.is_const || HIDDEN.contains(&decl.name.as_str())
+}
+
+// The number of declarations of boxes at the end of a class, like
+// `PixelAspectRatioBox pasp;`, `ChannelLayout();` or `Box ();`.
+pub fn count_slots(stmts: &[Stmt]) -> usize {
+    stmts.iter().rev().take_while(|s| match s {
+        &Stmt::VarDecl(decl) => {
+            let is_box = decl.base_type == "Box" || decl.base_type.ends_with("Box") || decl.name.is_empty();
+            is_box && !is_hidden(decl) && decl.size == 0
+        },
+        _ => false,
+    }).count()
+}
+
+// Name of the field of a declaration.
+pub fn decl_name(decl: &VarDecl) -> String {
+    if !decl.name.is_empty() {
+        decl.name.clone()
+    } else if decl.base_type == "Box" {
+        "boxes".to_string()
+    } else {
+        decl.base_type.clone()
+    }
+}
+
+// Names for the Vec and the entry struct of a loop,
+// derived from the count: `entry_count` -> `entries`, `entry`.
+fn count_names(count: &Expr) -> (String, String) {
+    if let Expr::Variable(v) = count.strip_parens() {
+        let name = snake_case(&v.to_string());
+        let mut name = name.as_str();
+        for prefix in &[ "num_", "number_of_" ] {
+            if name.starts_with(prefix) {
+                name = &name[prefix.len()..];
+            }
+        }
+        for suffix in &[ "_count", "count" ] {
+            if name.ends_with(suffix) {
+                name = &name[..name.len() - suffix.len()];
+            }
+        }
+        if !name.is_empty() {
+            return (plural(name), singular(name));
+        }
+    }
+    ("entries".to_string(), "entry".to_string())
+}
+
+fn plural(name: &str) -> String {
+    if name.ends_with('s') {
+        name.to_string()
+    } else if let Some(stem) = name.strip_suffix('y') {
+        format!("{}ies", stem)
+    } else {
+        format!("{}s", name)
+    }
+}
+
+fn singular(name: &str) -> String {
+    if let Some(stem) = name.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if name.ends_with('s') && !name.ends_with("ss") {
+        name[..name.len() - 1].to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// camelCase, CamelCase, snake_case, SOME_Thing -> snake_case.
+pub fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut res = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).map(|c| c.is_lowercase()).unwrap_or(false);
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                res.push('_');
+            }
+        }
+        res.extend(c.to_lowercase());
+    }
+    res
+}
+
+// snake_case -> CamelCase.
+pub fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|s| {
+            let mut c = s.chars();
+            match c.next() {
+                Some(first) => first.to_uppercase().chain(c).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// Name of a field in rust.
+pub fn rust_name(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "break", "const", "continue", "crate", "else", "enum", "extern",
+        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+        "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super",
+        "trait", "true", "type", "unsafe", "use", "where", "while", "async",
+        "await", "dyn", "abstract", "become", "box", "do", "final", "macro",
+        "override", "priv", "typeof", "unsized", "virtual", "yield", "try",
+    ];
+    let name = snake_case(name);
+    if KEYWORDS.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
@@ -0,0 +1,612 @@
+// Generate the `ReadFrom` implementation of a class.
+use proc_macro2::{Literal, Span, TokenStream};
+use quote::{quote, format_ident};
+use syn::Ident;
+
+use crate::{Class, Init, Stmt, VarDecl, IfElse, For, BinOp};
+use crate::codegen::{self, Scope};
+use crate::model::{self, Field, Model, Root, Type, Value};
+
+pub fn gen_read(class: &Class, model: &Model) -> TokenStream {
+    let name = &model.name;
+    let mut scope = Scope::reader(model);
+
+    // The header.
+    let mut header = TokenStream::new();
+    let is_next = gen_is_next(model);
+    if model.root == Root::Box || model.root == Root::FullBox {
+        header.extend(quote! {
+            let (__header, mut __body) = __r.read_box()?;
+            let __r = &mut __body;
+            size_form = __header.size_form;
+        });
+        match model.fourcc {
+            Value::Lit(ref e) => {
+                if let Some(fourcc) = codegen::fourcc(e) {
+                    header.extend(quote!(__r.expect_fourcc(&__header, #fourcc)?;));
+                }
+            },
+            Value::Var(ref v) => header.extend(assign_header(model, v, quote!(__header.fourcc.0))),
+            Value::None => {},
+        }
+        if let Some(u) = model.usertype {
+            header.extend(quote!(__r.expect_usertype(&__header, ::iso_bmff::Uuid([#(#u),*]))?;));
+        }
+    }
+    if model.root == Root::FullBox {
+        header.extend(quote! {
+            let __version = __r.read_uint(8)? as u8;
+            let __flags = __r.read_uint(24)? as u32;
+        });
+        match model.version {
+            Value::Lit(ref e) => {
+                let v = scope.expr_int(e);
+                header.extend(quote!(__r.expect_version(&__header, __version, #v as u8)?;));
+            },
+            Value::Var(ref v) => header.extend(assign_header(model, v, quote!(__version))),
+            Value::None => {},
+        }
+        if let Value::Var(ref v) = model.flags {
+            header.extend(assign_header(model, v, quote!(__flags)));
+        }
+    }
+    let finish = if model.root == Root::Box || model.root == Root::FullBox {
+        quote!(__r.finish(&__header)?;)
+    } else {
+        // Classes that are not a box are part of another box, which
+        // checks for trailing data.
+        quote!()
+    };
+
+    let check = check_aligned(model);
+    let locals = locals(model, 0);
+    let (args, export) = class_args(model);
+    let stmts = &class.body.statements.0;
+    let mut body = read_stmts(&mut scope, &stmts[.. stmts.len() - model.slots]);
+    body.extend(read_slots(&scope, model.slot_decls(&class.body.statements)));
+    if model.container {
+        body.extend(quote! {
+            while __r.left() > 0 {
+                boxes.push(<::iso_bmff::BoxNode as ::iso_bmff::ReadFrom>::read_from(__r)?);
+            }
+        });
+    }
+    let fields = model.struct_fields(0).map(|f| &f.ident);
+
+    quote! {
+        impl ::iso_bmff::ReadFrom for #name {
+            #[allow(unused_mut, unused_variables, unused_assignments, unused_parens)]
+            fn read_from(__r: &mut ::iso_bmff::BoxReader) -> ::std::io::Result<Self> {
+                #check
+                #locals
+                #args
+                #header
+                #export
+                #body
+                #check
+                #finish
+                Ok(#name {
+                    #(#fields,)*
+                })
+            }
+
+            #is_next
+        }
+    }
+}
+
+// Is one of this class next. For a box, if it has the fourcc and the
+// usertype of the class. A box with a fourcc that is a variable, or a
+// class that is not a box, can be anything, unless it is placed (see
+// `Placement`): then it must be in one of the parents, and in a track
+// with one of the handlers, if the handler is known. A class that is
+// not a box also needs the values of its bound arguments, like a
+// `grouping_type` of "roll" for `VisualRollRecoveryEntry`.
+fn gen_is_next(model: &Model) -> TokenStream {
+    let placement = &model.placement;
+    let is_box = model.root == Root::Box || model.root == Root::FullBox;
+    let mut conds = Vec::new();
+    match model.fourcc {
+        Value::Lit(ref e) if is_box => match codegen::fourcc(e) {
+            Some(fourcc) => conds.push(quote!(__h.fourcc == #fourcc)),
+            None => return quote!(),
+        },
+        Value::Var(_) if is_box && !placement.is_empty() => {
+            if !placement.fourcc.is_empty() {
+                let list = placement.fourcc.iter().map(|s| codegen::fourcc_str(s));
+                conds.push(quote!([#(#list),*].contains(&__h.fourcc)));
+            }
+        },
+        _ if !is_box && !placement.is_empty() => {
+            conds.push(quote!(__r.left() > 0));
+            for (name, value) in &model.bound {
+                let value = match codegen::fourcc(value) {
+                    Some(fourcc) => quote!(#fourcc.0 as i64),
+                    None => quote!((#value as i64)),
+                };
+                conds.push(quote!(__r.var(#name) == Some(#value)));
+            }
+        },
+        _ => return quote!(),
+    }
+    if let Some(u) = model.usertype {
+        conds.push(quote!(__r.peek_usertype().ok().flatten() == Some(::iso_bmff::Uuid([#(#u),*]))));
+    }
+    if !placement.parent.is_empty() {
+        let list = placement.parent.iter().map(|s| codegen::fourcc_str(s));
+        conds.push(quote!([#(#list),*].contains(&__r.parent())));
+    }
+    if !placement.handler.is_empty() {
+        let list = placement.handler.iter().map(|s| codegen::fourcc_str(s));
+        conds.push(quote! {
+            __r.var("handler_type").map_or(true, |__t| [#(#list),*].contains(&::iso_bmff::FourCC(__t as u32)))
+        });
+    }
+    let header = if is_box {
+        quote! {
+            let __h = match __r.peek_header() {
+                Ok(h) => h,
+                Err(_) => return false,
+            };
+        }
+    } else {
+        quote!()
+    };
+    quote! {
+        #[allow(unused_variables)]
+        fn is_next(__r: &::iso_bmff::BoxReader) -> bool {
+            #header
+            #(#conds)&&*
+        }
+    }
+}
+
+// Check the alignment at the start and end of the class.
+fn check_aligned(model: &Model) -> TokenStream {
+    match model.aligned {
+        Some(bits) => {
+            let bits = Literal::u32_unsuffixed(bits);
+            quote!(__r.check_aligned(#bits)?;)
+        },
+        None => quote!(),
+    }
+}
+
+// The arguments of the class are passed by the class that reads it, or
+// else they are known from an enclosing box. Once the header is read,
+// they are passed on to the boxes inside this one.
+fn class_args(model: &Model) -> (TokenStream, TokenStream) {
+    if model.args.is_empty() {
+        return (quote!(), quote!());
+    }
+    let mut args = quote!(let __args = __r.take_args(););
+    let mut export = TokenStream::new();
+    for (idx, name) in model.args.iter().enumerate() {
+        let field = match model.field(&[0], name) {
+            Some(field) => field,
+            None => continue,
+        };
+        let ident = &field.ident;
+        let ty = field.ty.tokens();
+        args.extend(quote!(#ident = __r.class_arg(&__args, #idx, #name) as #ty;));
+        export.extend(quote!(__r.set_var(#name, #ident as i64);));
+    }
+    (args, export)
+}
+
+// Set a field from the header.
+fn assign_header(model: &Model, name: &str, value: TokenStream) -> TokenStream {
+    match model.field(&[0], name) {
+        Some(field) => {
+            let ident = &field.ident;
+            let ty = field.ty.tokens();
+            match field.ty {
+                Type::Rust(_) => quote!(#ident = <#ty>::from_raw(#value as _);),
+                _ => quote!(#ident = #value as #ty;),
+            }
+        },
+        None => quote!(),
+    }
+}
+
+// Declare the fields of a level as local variables.
+fn locals(model: &Model, level: usize) -> TokenStream {
+    let locals = model.levels[level].fields.iter().map(|f| {
+        let ident = &f.ident;
+        let ty = f.tokens();
+        quote!(let mut #ident: #ty = Default::default();)
+    });
+    quote!(#(#locals)*)
+}
+
+fn read_stmts(scope: &mut Scope, stmts: &[Stmt]) -> TokenStream {
+    let mut out = TokenStream::new();
+    for stmt in stmts {
+        out.extend(match stmt {
+            Stmt::VarDecl(decl) => read_decl(scope, decl),
+            Stmt::If(ifelse) => read_if(scope, ifelse),
+            Stmt::For(f) => read_for(scope, f),
+        });
+    }
+    out
+}
+
+fn read_if(scope: &mut Scope, ifelse: &IfElse) -> TokenStream {
+    let cond = scope.expr_bool(&ifelse.ifexpr);
+    let if_true = read_stmts(scope, &ifelse.if_true.0);
+    let mut out = quote!(if #cond { #if_true });
+    for i in &ifelse.if_else {
+        let cond = scope.expr_bool(&i.ifexpr);
+        let if_true = read_stmts(scope, &i.if_true.0);
+        out.extend(quote!(else if #cond { #if_true }));
+    }
+    if !ifelse.if_false.0.is_empty() {
+        let if_false = read_stmts(scope, &ifelse.if_false.0);
+        out.extend(quote!(else { #if_false }));
+    }
+    out
+}
+
+fn read_for(scope: &mut Scope, f: &For) -> TokenStream {
+    let model = scope.model;
+    let idx = model.loops[&(f as *const For)];
+    let level = &model.levels[idx];
+    let target = model.loop_field(idx);
+    let list = Ident::new(&format!("__list{}", idx), Span::call_site());
+
+    let range = match (&f.op, model.loop_end(f)) {
+        (Some(op), Some(end)) => {
+            let start = f.start as i64;
+            let end = scope.expr_int(end);
+            match op {
+                &BinOp::Le(_) => quote!(for _ in #start ..= #end),
+                _ => quote!(for _ in #start .. #end),
+            }
+        },
+        // no end: to the end of the box.
+        _ => quote!(while __r.left() > 0),
+    };
+    // A loop to the end of the box must get there.
+    let (start, check) = match model.loop_end(f) {
+        Some(_) if f.op.is_some() => (quote!(), quote!()),
+        _ => {
+            let msg = format!("{}: entry of `{}` is empty", model.name, target);
+            (quote!(let __start = __r.left_bits();), quote! {
+                if __r.left_bits() == __start {
+                    return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, #msg));
+                }
+            })
+        },
+    };
+
+    scope.levels.push(idx);
+    let locals = locals(model, idx);
+    let body = read_stmts(scope, &f.statements.0);
+    scope.levels.pop();
+
+    let entry = if level.inline {
+        let ident = &level.fields[0].ident;
+        quote!(#ident)
+    } else {
+        let name = Ident::new(&level.name, Span::call_site());
+        let fields = model.struct_fields(idx).map(|f| &f.ident);
+        quote!(#name { #(#fields,)* })
+    };
+
+    quote! {
+        let mut #list = Vec::new();
+        #range {
+            #start
+            #locals
+            #body
+            #check
+            #list.push(#entry);
+        }
+        #target = #list;
+    }
+}
+
+// Read the child boxes at the end of a box, in any order. A box goes
+// into the first field that takes it, other boxes into a `Box ();`.
+// A field that is not optional must be there. The order is kept in
+// `child_order`, unless it is the order of the declarations.
+fn read_slots<'a>(scope: &Scope, decls: impl Iterator<Item = &'a VarDecl>) -> TokenStream {
+    let mut seen = Vec::new();
+    let mut arms = Vec::new();
+    let mut any = Vec::new();
+    let mut missing = Vec::new();
+    let mut other = None;
+    for (idx, decl) in decls.enumerate() {
+        let name = model::decl_name(decl);
+        let field = match scope.model.field(&[0], &name) {
+            Some(field) => field,
+            None => continue,
+        };
+        let ident = &field.ident;
+        match field.ty {
+            Type::Vec(ref t) if **t == Type::Node => {
+                let idx = idx as u8;
+                other = Some(quote! {
+                    #ident.push(<::iso_bmff::BoxNode as ::iso_bmff::ReadFrom>::read_from(__r)?);
+                    child_order.push(#idx);
+                });
+            },
+            Type::Vec(ref t) => {
+                let value = read_value(scope, decl, Some(t));
+                let t = t.tokens();
+                let idx = idx as u8;
+                arms.push(quote! {
+                    if <#t as ::iso_bmff::ReadFrom>::is_next(__r) {
+                        #ident.push(#value);
+                        child_order.push(#idx);
+                        continue;
+                    }
+                });
+            },
+            ref t => {
+                let flag = format_ident!("__seen_{}", idx);
+                let value = read_value(scope, decl, Some(t));
+                let value = if field.optional {
+                    quote!(Some(#value))
+                } else {
+                    let msg = format!("missing `{}`", name);
+                    missing.push(quote! {
+                        if !#flag {
+                            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, #msg));
+                        }
+                    });
+                    value
+                };
+                let arm = {
+                    let t = t.tokens();
+                    let idx = idx as u8;
+                    quote! {
+                        if !#flag && <#t as ::iso_bmff::ReadFrom>::is_next(__r) {
+                            #ident = #value;
+                            #flag = true;
+                            child_order.push(#idx);
+                            continue;
+                        }
+                    }
+                };
+                seen.push(quote!(let mut #flag = false;));
+                // A `Box` takes any box, the others go first.
+                if *t == Type::AnyBox {
+                    any.push(arm);
+                } else {
+                    arms.push(arm);
+                }
+            },
+        }
+    }
+    arms.extend(any);
+    if arms.is_empty() && other.is_none() {
+        return quote!();
+    }
+    let other = other.unwrap_or_else(|| quote!(break;));
+    quote! {
+        #(#seen)*
+        while __r.left() > 0 {
+            #(#arms)*
+            #other
+        }
+        if child_order.windows(2).all(|__w| __w[0] <= __w[1]) {
+            child_order.clear();
+        }
+        #(#missing)*
+    }
+}
+
+// Read a `const` int, and check its value. Otherwise the box would
+// not be written back the same, and it may be another class, like an
+// `AudioSampleEntryV1` where `AudioSampleEntry` has a reserved 0.
+fn read_const(scope: &Scope, decl: &VarDecl) -> Option<TokenStream> {
+    let expected = match (decl.base_type.as_str(), &decl.default) {
+        ("int" | "uint" | "bit", &Some(Init::Value(ref e))) if decl.is_const => scope.expr_int(e),
+        _ => return None,
+    };
+    let count = match decl.array {
+        Some(ref array) => match array.get_lit_int() {
+            Ok(n) if n > 0 => n,
+            _ => return None,
+        },
+        None => 1,
+    };
+    let value = read_value(scope, decl, None);
+    let msg = format!("invalid value of `{}`", decl.name);
+    Some(quote! {
+        for _ in 0 .. #count {
+            if #value as i64 != #expected as i64 {
+                return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, #msg));
+            }
+        }
+    })
+}
+
+// Read a declaration, and assign it to its field.
+fn read_decl(scope: &mut Scope, decl: &VarDecl) -> TokenStream {
+    if model::is_hidden(decl) {
+        if let Some(check) = read_const(scope, decl) {
+            return check;
+        }
+        // Read it, and throw it away.
+        let value = read_array(scope, decl, None);
+        return quote!(let _ = #value;);
+    }
+    let name = model::decl_name(decl);
+    let field = match scope.model.field(&scope.levels, &name) {
+        Some(field) => field,
+        None => return quote!(),
+    };
+    let value = read_array(scope, decl, Some(field));
+    let ident = &field.ident;
+    let export = scope.export(field, quote!(__r));
+    if field.trailing {
+        // # optional: only if it is there.
+        let present = match field.ty {
+            Type::Class(_) | Type::Rust(_) => {
+                let t = field.ty.tokens();
+                quote!(<#t as ::iso_bmff::ReadFrom>::is_next(__r))
+            },
+            // Not if what is left is one of the child boxes, like the
+            // `BitRateBox` after the strings of an `XMLSubtitleSampleEntry`,
+            // or any box if the class takes any box.
+            _ => {
+                let types: Vec<_> = scope.model.slot_fields.iter()
+                    .filter_map(|name| scope.model.field(&[0], name))
+                    .map(|f| match f.ty {
+                        Type::Vec(ref t) => &**t,
+                        ref t => t,
+                    })
+                    .collect();
+                let boxes = types.iter()
+                    .filter(|t| matches!(t, Type::Class(_) | Type::Rust(_)))
+                    .map(|t| t.tokens());
+                let any = match types.iter().any(|t| matches!(t, Type::Node | Type::AnyBox)) {
+                    true => quote!(&& !__r.is_boxes()),
+                    false => quote!(),
+                };
+                quote!(__r.left() > 0 #(&& !<#boxes as ::iso_bmff::ReadFrom>::is_next(__r))* #any)
+            },
+        };
+        quote!(if #present { #ident = Some(#value); } #export)
+    } else if field.optional {
+        quote!(#ident = Some(#value); #export)
+    } else {
+        quote!(#ident = #value; #export)
+    }
+}
+
+// Read a declaration, which might be an array.
+fn read_array(scope: &Scope, decl: &VarDecl, field: Option<&Field>) -> TokenStream {
+    let ty = field.map(|f| &f.ty);
+    let elem_ty = match ty {
+        Some(&Type::Array(ref t, _)) | Some(&Type::Vec(ref t)) => Some(&**t),
+        other => other,
+    };
+    let elem = read_value(scope, decl, elem_ty);
+
+    let array = match decl.array {
+        Some(ref array) => array,
+        None => {
+            if decl.name.is_empty() && decl.base_type == "Box" {
+                // "Box ();" means any number of boxes.
+                return quote!({
+                    let mut __v = Vec::new();
+                    while __r.left() > 0 {
+                        __v.push(#elem);
+                    }
+                    __v
+                });
+            }
+            return elem;
+        },
+    };
+    if decl.base_type == "string" {
+        // string[32] is a fixed size string.
+        let size = array.get_lit_int().unwrap_or(0) as usize;
+        return quote!(__r.read_fixed_string(#size)?);
+    }
+    if let Some(&Type::Array(ref t, size)) = ty {
+        let t = t.tokens();
+        let size = size as usize;
+        return quote!({
+            let mut __a: [#t; #size] = Default::default();
+            for __x in __a.iter_mut() {
+                *__x = #elem;
+            }
+            __a
+        });
+    }
+    match array.get_lit_int() {
+        // [] is "to the end of the box".
+        Ok(0) => match (decl.base_type.as_str(), elem_ty) {
+            (_, Some(&Type::Int { bits: 8, signed: false })) => {
+                // Bytes, like the data of a `mdat` box.
+                quote!(__r.read(__r.left())?.to_vec())
+            },
+            ("int" | "uint" | "bit", _) if decl.size_expr.is_none() => {
+                let name = model::decl_name(decl);
+                let bits = decl.size;
+                quote!({
+                    __r.check_elements(#name, #bits)?;
+                    let mut __v = Vec::with_capacity((__r.left_bits() / #bits as u64) as usize);
+                    while __r.left_bits() > 0 {
+                        __v.push(#elem);
+                    }
+                    __v
+                })
+            },
+            _ => quote!({
+                let mut __v = Vec::new();
+                while __r.left() > 0 {
+                    __v.push(#elem);
+                }
+                __v
+            }),
+        },
+        _ => {
+            let count = scope.expr_int(array);
+            quote!({
+                let mut __v = Vec::new();
+                for _ in 0 .. #count {
+                    __v.push(#elem);
+                }
+                __v
+            })
+        },
+    }
+}
+
+// Read a single value of a declaration. `ty` is the type of the
+// field it ends up in, it is `None` for hidden fields.
+fn read_value(scope: &Scope, decl: &VarDecl, ty: Option<&Type>) -> TokenStream {
+    match decl.base_type.as_str() {
+        "int" | "uint" | "bit" => {
+            let bits = match decl.size_expr {
+                Some(ref e) => {
+                    let e = scope.expr_int(e);
+                    quote!((#e as u32))
+                },
+                None => {
+                    let bits = Literal::u32_unsuffixed(decl.size);
+                    quote!(#bits)
+                },
+            };
+            let value = if decl.signed {
+                quote!(__r.read_int(#bits)?)
+            } else {
+                quote!(__r.read_uint(#bits)?)
+            };
+            match ty {
+                Some(&Type::Bool) => quote!((#value != 0)),
+                Some(&Type::Rust(_)) | Some(&Type::Class(_)) => {
+                    let t = ty.unwrap().tokens();
+                    quote!(<#t as ::iso_bmff::ReadFrom>::read_from(__r)?)
+                },
+                Some(t) => {
+                    let t = t.tokens();
+                    quote!((#value as #t))
+                },
+                None => value,
+            }
+        },
+        "string" => quote!(__r.read_string()?),
+        "Box" => quote!(<::iso_bmff::RawBox as ::iso_bmff::ReadFrom>::read_from(__r)?),
+        class => {
+            let t = match ty {
+                Some(t) => t.tokens(),
+                None => {
+                    let t = Ident::new(class, decl.type_span);
+                    quote!(#t)
+                },
+            };
+            if decl.params.is_empty() {
+                quote!(<#t as ::iso_bmff::ReadFrom>::read_from(__r)?)
+            } else {
+                let params = decl.params.iter().map(|p| scope.expr_int(p));
+                quote!(__r.read_with_args::<#t>(vec![#(#params),*])?)
+            }
+        },
+    }
+}
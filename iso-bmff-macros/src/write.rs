@@ -0,0 +1,466 @@
+// Generate the `WriteTo` implementation of a class.
+use proc_macro2::{Literal, Span, TokenStream};
+use quote::{quote, format_ident};
+use syn::Ident;
+
+use crate::{Class, Init, Stmt, VarDecl, IfElse, For, BinOp};
+use crate::expr::Expr;
+use crate::codegen::{self, Scope};
+use crate::model::{self, Model, Root, Type, Value};
+
+pub fn gen_write(class: &Class, model: &Model) -> TokenStream {
+    let name = &model.name;
+    let mut scope = Scope::writer(model);
+
+    let locals = locals(model, 0, quote!(self));
+
+    // The header.
+    let mut header = TokenStream::new();
+    let is_box = model.root == Root::Box || model.root == Root::FullBox;
+    if is_box {
+        let fourcc = match model.fourcc {
+            Value::Lit(ref e) => codegen::fourcc(e).unwrap_or(quote!(::iso_bmff::FourCC(0))),
+            Value::Var(ref v) => {
+                let v = scope.expr_int(&var(v));
+                quote!(::iso_bmff::FourCC(#v as u32))
+            },
+            Value::None => quote!(::iso_bmff::FourCC(0)),
+        };
+        header.extend(quote!(let __start = __w.begin_box_sized(#fourcc, *size_form)?;));
+        if let Some(u) = model.usertype {
+            header.extend(quote!(__w.write(&[#(#u),*])?;));
+        }
+    }
+    if model.root == Root::FullBox {
+        let value = |v: &Value| match v {
+            Value::Lit(e) => scope.expr_int(e),
+            Value::Var(v) => scope.expr_int(&var(v)),
+            &Value::None => quote!(0),
+        };
+        // A box with versions that have a different layout is
+        // written with the version that `write_to` picks.
+        if model.versions.is_empty() {
+            let version = value(&model.version);
+            header.extend(quote!(let __version = #version as u8;));
+        }
+        let flags = value(&model.flags);
+        let bits = flag_bits(model);
+        header.extend(quote! {
+            let mut __flags = #flags as u32;
+            #bits
+            __w.write_uint(8, __version as u64)?;
+            __w.write_uint(24, __flags as u64)?;
+        });
+    }
+    let finish = if is_box {
+        quote!(__w.end_box(__start)?;)
+    } else {
+        quote!()
+    };
+
+    let stmts = &class.body.statements.0;
+    let mut body = write_stmts(&mut scope, &stmts[.. stmts.len() - model.slots]);
+    body.extend(write_slots(&scope, model.slot_decls(&class.body.statements)));
+    if model.container {
+        body.extend(quote! {
+            for __b in boxes.iter() {
+                ::iso_bmff::WriteTo::write_to(__b, __w)?;
+            }
+        });
+    }
+    let check = match model.aligned {
+        Some(bits) => {
+            let bits = Literal::u32_unsuffixed(bits);
+            quote!(__w.check_aligned(#bits)?;)
+        },
+        None => quote!(),
+    };
+
+    let version = match model.version_field() {
+        Some(field) if !model.versions.is_empty() => field,
+        _ => return quote! {
+            impl ::iso_bmff::WriteTo for #name {
+                #[allow(unused_variables, unused_parens)]
+                fn write_to(&self, __w: &mut ::iso_bmff::BoxWriter) -> ::std::io::Result<()> {
+                    #check
+                    #locals
+                    #header
+                    #body
+                    #check
+                    #finish
+                    Ok(())
+                }
+            }
+        },
+    };
+
+    // Keep the version, unless it cannot hold the values.
+    let ident = &version.ident;
+    let ty = version.ty.tokens();
+    quote! {
+        impl #name {
+            #[allow(unused_variables, unused_parens)]
+            fn write_version(&self, __w: &mut ::iso_bmff::BoxWriter, __version: u8) -> ::std::io::Result<()> {
+                #check
+                #locals
+                let #ident: &#ty = &(__version as #ty);
+                #header
+                #body
+                #check
+                #finish
+                Ok(())
+            }
+        }
+
+        impl ::iso_bmff::WriteTo for #name {
+            fn write_to(&self, __w: &mut ::iso_bmff::BoxWriter) -> ::std::io::Result<()> {
+                self.write_version(__w, self.fitting_version() as u8)
+            }
+        }
+    }
+}
+
+// An expression for a variable.
+fn var(name: &str) -> crate::expr::Expr {
+    crate::expr::Expr::Variable(Ident::new(name, Span::call_site()))
+}
+
+// Bind the fields of a level to local variables (references),
+// and compute the derived fields.
+fn locals(model: &Model, level: usize, value: impl quote::ToTokens) -> TokenStream {
+    let fields = model.struct_fields(level).map(|f| &f.ident);
+    let mut out = if model.levels[level].inline {
+        let ident = &model.levels[level].fields[0].ident;
+        quote!(let #ident = #value;)
+    } else {
+        let name = if level == 0 {
+            model.name.clone()
+        } else {
+            Ident::new(&model.levels[level].name, Span::call_site())
+        };
+        quote!(let #name { #(#fields,)* } = #value;)
+    };
+    for f in &model.levels[level].fields {
+        if let Some((ref target, offset)) = f.derived {
+            let ident = &f.ident;
+            out.extend(quote!(let #ident: &i64 = &(#target.len() as i64 + #offset);));
+        }
+    }
+    out
+}
+
+// The bits of the flags that say if an optional field is present are
+// set from the fields, and the flags are bound to the result.
+fn flag_bits(model: &Model) -> TokenStream {
+    let field = match model.flags {
+        Value::Var(ref v) => model.field(&[0], v).unwrap(),
+        _ => return quote!(),
+    };
+    let mut masks: Vec<(i64, Vec<TokenStream>)> = Vec::new();
+    for (idx, level) in model.levels.iter().enumerate() {
+        // only loops directly in the class.
+        let target = if idx > 0 { Some(model.loop_field(idx)) } else { None };
+        if idx > 0 && !model.levels[0].fields.iter().any(|f| Some(&f.ident) == target.as_ref()) {
+            continue;
+        }
+        for f in level.fields.iter() {
+            let mask = match f.flag {
+                Some(mask) if f.optional => mask,
+                _ => continue,
+            };
+            let ident = &f.ident;
+            // in a loop, all entries must agree.
+            let present = match target {
+                None => quote!(Some(#ident.is_some())),
+                Some(ref t) => {
+                    let msg = format!("{} is set in some of {} but not in all", f.name, t);
+                    quote!({
+                        let mut __all = #t.iter().map(|__e| __e.#ident.is_some());
+                        let __first = __all.next();
+                        if __all.any(|__p| Some(__p) != __first) {
+                            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, #msg));
+                        }
+                        __first
+                    })
+                },
+            };
+            match masks.iter_mut().find(|m| m.0 == mask) {
+                Some(m) => m.1.push(present),
+                None => masks.push((mask, vec![ present ])),
+            }
+        }
+    }
+    if masks.is_empty() {
+        return quote!();
+    }
+    let mut out = TokenStream::new();
+    for (mask, present) in masks {
+        let mask = mask as u32;
+        out.extend(quote! {
+            let __present = [#(#present),*];
+            if __present.contains(&Some(true)) {
+                __flags |= #mask;
+            } else if __present.contains(&Some(false)) {
+                __flags &= !#mask;
+            }
+        });
+    }
+    let ident = &field.ident;
+    let ty = field.ty.tokens();
+    out.extend(quote!(let #ident: &#ty = &<#ty as From<u32>>::from(__flags);));
+    out
+}
+
+fn write_stmts(scope: &mut Scope, stmts: &[Stmt]) -> TokenStream {
+    let mut out = TokenStream::new();
+    for stmt in stmts {
+        out.extend(match stmt {
+            Stmt::VarDecl(decl) => write_decl(scope, decl),
+            Stmt::If(ifelse) => write_if(scope, ifelse),
+            Stmt::For(f) => write_for(scope, f),
+        });
+    }
+    out
+}
+
+fn write_if(scope: &mut Scope, ifelse: &IfElse) -> TokenStream {
+    let cond = scope.expr_bool(&ifelse.ifexpr);
+    let if_true = write_stmts(scope, &ifelse.if_true.0);
+    let mut out = quote!(if #cond { #if_true });
+    for i in &ifelse.if_else {
+        let cond = scope.expr_bool(&i.ifexpr);
+        let if_true = write_stmts(scope, &i.if_true.0);
+        out.extend(quote!(else if #cond { #if_true }));
+    }
+    if !ifelse.if_false.0.is_empty() {
+        let if_false = write_stmts(scope, &ifelse.if_false.0);
+        out.extend(quote!(else { #if_false }));
+    }
+    out
+}
+
+fn write_for(scope: &mut Scope, f: &For) -> TokenStream {
+    let model = scope.model;
+    let idx = model.loops[&(f as *const For)];
+    let target = model.loop_field(idx);
+
+    let check = match (&f.op, model.loop_end(f)) {
+        (Some(op), Some(end)) => {
+            let start = f.start as i64;
+            let end = scope.expr_int(end);
+            let count = match op {
+                &BinOp::Le(_) => quote!(#end - #start + 1),
+                _ => quote!(#end - #start),
+            };
+            let name = target.to_string();
+            quote!(__w.check_count(#name, #target.len(), #count)?;)
+        },
+        _ => quote!(),
+    };
+
+    scope.levels.push(idx);
+    let locals = locals(model, idx, quote!(__e));
+    let body = write_stmts(scope, &f.statements.0);
+    scope.levels.pop();
+
+    quote! {
+        #check
+        for __e in #target.iter() {
+            #locals
+            #body
+        }
+    }
+}
+
+// Write the child boxes at the end of a box, in the order in which
+// they were read (`child_order`). The rest, like all the boxes of a
+// box that was not read, follow in the order of the declarations.
+fn write_slots<'a>(scope: &Scope, decls: impl Iterator<Item = &'a VarDecl>) -> TokenStream {
+    let mut slots = Vec::new();
+    let mut arms = Vec::new();
+    let mut rest = Vec::new();
+    for (idx, decl) in decls.enumerate() {
+        let name = model::decl_name(decl);
+        let field = match scope.model.field(&[0], &name) {
+            Some(field) => field,
+            None => continue,
+        };
+        let ident = &field.ident;
+        let (elem_ty, iter) = match field.ty {
+            Type::Vec(ref t) => (&**t, quote!(#ident.iter())),
+            ref t if field.optional => (t, quote!(#ident.iter())),
+            ref t => (t, quote!(::std::iter::once(#ident))),
+        };
+        let elem = write_value(scope, decl, elem_ty, quote!(__x));
+        let slot = format_ident!("__slot_{}", idx);
+        let idx = idx as u8;
+        slots.push(quote!(let mut #slot = #iter;));
+        arms.push(quote!(#idx => if let Some(__x) = #slot.next() { #elem }));
+        rest.push(quote!(for __x in #slot { #elem }));
+    }
+    if slots.is_empty() {
+        return quote!();
+    }
+    quote! {
+        #(#slots)*
+        for __i in child_order.iter() {
+            match *__i {
+                #(#arms,)*
+                _ => {},
+            }
+        }
+        #(#rest)*
+    }
+}
+
+// Write a declaration.
+fn write_decl(scope: &mut Scope, decl: &VarDecl) -> TokenStream {
+    if model::is_hidden(decl) {
+        return write_hidden(scope, decl);
+    }
+    let name = model::decl_name(decl);
+    let field = match scope.model.field(&scope.levels, &name) {
+        Some(field) => field,
+        None => return quote!(),
+    };
+    let ident = &field.ident;
+    let elem_ty = match field.ty {
+        Type::Array(ref t, _) | Type::Vec(ref t) => &**t,
+        ref t => t,
+    };
+    let elem = write_value(scope, decl, elem_ty, quote!(__x));
+
+    let out = match decl.array {
+        Some(ref array) if decl.base_type == "string" => {
+            // string[32] is a fixed size string.
+            let size = array.get_lit_int().unwrap_or(0) as usize;
+            quote!(__w.write_fixed_string(#size, #ident)?;)
+        },
+        Some(ref array) if matches!(array.get_lit_int(), Ok(0)) && *elem_ty == (Type::Int { bits: 8, signed: false }) => {
+            // Bytes to the end of the box, like the data of a `mdat` box.
+            quote!(__w.write(#ident)?;)
+        },
+        Some(ref array) => {
+            let check = match (&field.ty, array.get_lit_int()) {
+                // fixed size, or [] "to the end of the box".
+                (&Type::Array(..), _) | (_, Ok(0)) => quote!(),
+                _ => {
+                    let count = scope.expr_int(array);
+                    let name = ident.to_string();
+                    quote!(__w.check_count(#name, #ident.len(), #count)?;)
+                },
+            };
+            quote! {
+                #check
+                for __x in #ident.iter() {
+                    #elem
+                }
+            }
+        },
+        None if field.ty != *elem_ty => {
+            // "Box ();", any number of boxes.
+            quote! {
+                for __x in #ident.iter() {
+                    #elem
+                }
+            }
+        },
+        None => quote! {
+            let __x = #ident;
+            #elem
+        },
+    };
+
+    let export = scope.export(field, quote!(__w));
+    if field.trailing {
+        // # optional: left out if it is not set.
+        quote!(if let Some(ref #ident) = *#ident { #out } #export)
+    } else if field.optional && field.derived.is_none() {
+        // If it is not set, write the default, so that the
+        // box still has the layout that the header says it has.
+        let ty = field.ty.tokens();
+        quote!({
+            let __d = <#ty as Default>::default();
+            let #ident = #ident.as_ref().unwrap_or(&__d);
+            #out
+        } #export)
+    } else {
+        quote!({ #out } #export)
+    }
+}
+
+// Write a single value, `value` is a reference to it.
+fn write_value(scope: &Scope, decl: &VarDecl, ty: &Type, value: TokenStream) -> TokenStream {
+    match decl.base_type.as_str() {
+        "int" | "uint" | "bit" if !matches!(ty, &Type::Rust(_) | &Type::Class(_)) => {
+            let bits = bits(scope, decl);
+            if decl.signed {
+                quote!(__w.write_int(#bits, *#value as i64)?;)
+            } else {
+                quote!(__w.write_uint(#bits, *#value as u64)?;)
+            }
+        },
+        "string" => quote!(__w.write_string(#value)?;),
+        _ => quote!(::iso_bmff::WriteTo::write_to(#value, __w)?;),
+    }
+}
+
+// Size in bits of an int(n).
+fn bits(scope: &Scope, decl: &VarDecl) -> TokenStream {
+    match decl.size_expr {
+        Some(ref e) => {
+            let e = scope.expr_int(e);
+            quote!((#e as u32))
+        },
+        None => {
+            let bits = Literal::u32_unsuffixed(decl.size);
+            quote!(#bits)
+        },
+    }
+}
+
+// Members that are not in the struct are written with their default value.
+fn write_hidden(scope: &Scope, decl: &VarDecl) -> TokenStream {
+    let value = |default: Option<&Expr>| match decl.base_type.as_str() {
+        "int" | "uint" | "bit" => {
+            let bits = bits(scope, decl);
+            let default = match default {
+                Some(e) => scope.expr_int(e),
+                None => quote!(0i64),
+            };
+            if decl.signed {
+                quote!(__w.write_int(#bits, #default)?;)
+            } else {
+                quote!(__w.write_uint(#bits, #default as u64)?;)
+            }
+        },
+        "string" => quote!(__w.write_string(&::iso_bmff::BoxString::default())?;),
+        class => {
+            let t = Ident::new(class, decl.type_span);
+            quote!(::iso_bmff::WriteTo::write_to(&<#t as Default>::default(), __w)?;)
+        },
+    };
+    if let Some(Init::List(ref list)) = decl.default {
+        // = { 1, 2, 3 }
+        return list.iter().map(|e| value(Some(e))).collect();
+    }
+    let value = match decl.default {
+        Some(Init::Value(ref e)) => value(Some(e)),
+        _ => value(None),
+    };
+    match decl.array {
+        Some(ref array) => match array.get_lit_int() {
+            // [] "to the end of the box" is empty.
+            Ok(0) => quote!(),
+            _ => {
+                let count = scope.expr_int(array);
+                quote! {
+                    for _ in 0 .. #count {
+                        #value
+                    }
+                }
+            },
+        },
+        None => value,
+    }
+}
@@ -0,0 +1,41 @@
+// Values that a class uses, but does not declare.
+use std::io;
+
+use crate::reader::invalid_data;
+
+/// Values of variables that a class uses, but that are not part of the
+/// class itself, like the `handler_type` of the `hdlr` box, or
+/// `track_is_audio` for the default volume of a `tkhd` box.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    vars:   Vec<(String, i64)>,
+}
+
+impl Context {
+    /// New, empty context.
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// Set the value of a variable.
+    pub fn set(&mut self, name: &str, value: i64) {
+        self.vars.retain(|v| v.0 != name);
+        self.vars.push((name.to_string(), value));
+    }
+
+    /// Set the value of a variable, builder style.
+    pub fn with(mut self, name: &str, value: i64) -> Context {
+        self.set(name, value);
+        self
+    }
+
+    /// The value of a variable, if it is known.
+    pub fn get(&self, name: &str) -> Option<i64> {
+        self.vars.iter().find(|v| v.0 == name).map(|v| v.1)
+    }
+
+    /// The value of a variable, or an error if it is not known.
+    pub fn lookup(&self, name: &str) -> io::Result<i64> {
+        self.get(name).ok_or_else(|| invalid_data(format!("unresolved variable `{}`", name)))
+    }
+}
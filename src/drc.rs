@@ -0,0 +1,27 @@
+// Dynamic Range Control boxes of ISO/IEC 23003-4, that an audio
+// sample entry can contain. Their contents are kept as bytes.
+use iso_bmff_macros::def_box;
+
+def_box! {
+    aligned(8) class DRCCoefficientsBasic extends FullBox("udc1", version = 0, 0) {
+       unsigned int(8) data[];
+    }
+}
+
+def_box! {
+    aligned(8) class DRCInstructionsBasic extends FullBox("udi1", version = 0, 0) {
+       unsigned int(8) data[];
+    }
+}
+
+def_box! {
+    aligned(8) class DRCCoefficientsUniDRC extends FullBox("udc2", version = 0, 0) {
+       unsigned int(8) data[];
+    }
+}
+
+def_box! {
+    aligned(8) class DRCInstructionsUniDRC extends FullBox("udi2", version = 0, 0) {
+       unsigned int(8) data[];
+    }
+}
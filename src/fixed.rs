@@ -0,0 +1,200 @@
+// Fixed point numbers, and the transformation matrix.
+use std::fmt;
+use std::io;
+
+use iso_bmff_macros::def_box;
+
+use crate::reader::{BoxReader, ReadFrom};
+use crate::writer::{BoxWriter, WriteTo};
+
+// A fixed point number, `$bits` bits of which `$frac` are the fraction,
+// read and written with `$read` and `$write`.
+macro_rules! def_fixed {
+    (
+        $(#[$doc:meta])* $name:ident($raw:ty), $bits:expr, $frac:expr, $read:ident, $write:ident
+    ) => {
+        $(#[$doc])*
+        ///
+        /// The field is the value as it is in the file, as are `from_raw`
+        /// and `to_raw`. `From` and `Into` an `f64` convert the number.
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(pub $raw);
+
+        impl $name {
+            /// The number closest to `v`.
+            pub fn from_f64(v: f64) -> $name {
+                $name((v * (1u64 << $frac) as f64).round() as $raw)
+            }
+
+            /// The number as a float.
+            pub fn to_f64(&self) -> f64 {
+                self.0 as f64 / (1u64 << $frac) as f64
+            }
+
+            /// The number for the value as it is in the file.
+            pub fn from_raw(v: $raw) -> $name {
+                $name(v)
+            }
+
+            /// The value as it is in the file.
+            pub fn to_raw(&self) -> $raw {
+                self.0
+            }
+        }
+
+        impl From<f64> for $name {
+            fn from(v: f64) -> $name {
+                $name::from_f64(v)
+            }
+        }
+
+        impl From<$name> for f64 {
+            fn from(v: $name) -> f64 {
+                v.to_f64()
+            }
+        }
+
+        impl ReadFrom for $name {
+            fn read_from(r: &mut BoxReader) -> io::Result<$name> {
+                Ok($name(r.$read($bits)? as $raw))
+            }
+        }
+
+        impl WriteTo for $name {
+            fn write_to(&self, w: &mut BoxWriter) -> io::Result<()> {
+                w.$write($bits, self.0 as _)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.to_f64())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.to_f64())
+            }
+        }
+    };
+}
+
+def_fixed! {
+    /// A 16.16 fixed point number, like the `rate` of `mvhd`.
+    Fixed16_16(i32), 32, 16, read_int, write_int
+}
+
+def_fixed! {
+    /// An unsigned 16.16 fixed point number, like the `width` of `tkhd`.
+    UFixed16_16(u32), 32, 16, read_uint, write_uint
+}
+
+def_fixed! {
+    /// An 8.8 fixed point number, like the `volume` of `tkhd`.
+    Fixed8_8(i16), 16, 8, read_int, write_int
+}
+
+def_fixed! {
+    /// A 2.30 fixed point number, like `u`, `v` and `w` of a `Matrix`.
+    Fixed2_30(i32), 32, 30, read_int, write_int
+}
+
+// The transformation matrix of `mvhd` and `tkhd`:
+//
+//     | a b u |
+//     | c d v |
+//     | x y w |
+//
+// A point (p, q) is displayed at (a * p + c * q + x, b * p + d * q + y).
+// The default is the unity matrix.
+def_box! {
+    class Matrix {
+        int(32) a = 0x00010000; # rust_type: Fixed16_16
+        int(32) b; # rust_type: Fixed16_16
+        int(32) u; # rust_type: Fixed2_30
+        int(32) c; # rust_type: Fixed16_16
+        int(32) d = 0x00010000; # rust_type: Fixed16_16
+        int(32) v; # rust_type: Fixed2_30
+        int(32) x; # rust_type: Fixed16_16
+        int(32) y; # rust_type: Fixed16_16
+        int(32) w = 0x40000000; # rust_type: Fixed2_30
+    }
+}
+
+impl Matrix {
+    /// A matrix that rotates by 0, 90, 180 or 270 degrees clockwise,
+    /// without translation. `None` for other angles.
+    pub fn for_rotation(degrees: u32) -> Option<Matrix> {
+        let (cos, sin) = match degrees {
+            0 => (1, 0),
+            90 => (0, 1),
+            180 => (-1, 0),
+            270 => (0, -1),
+            _ => return None,
+        };
+        Some(Matrix {
+            a: Fixed16_16(cos << 16),
+            b: Fixed16_16(sin << 16),
+            c: Fixed16_16(-sin << 16),
+            d: Fixed16_16(cos << 16),
+            ..Matrix::default()
+        })
+    }
+
+    /// The rotation, in degrees clockwise: 0, 90, 180 or 270. `None` if
+    /// the matrix does more than rotate by one of those, like scaling.
+    /// The translation is ignored.
+    pub fn rotation(&self) -> Option<u32> {
+        let rotation = [ 0, 90, 180, 270 ].iter().find(|&&r| {
+            let m = Matrix::for_rotation(r).unwrap();
+            (m.a, m.b, m.c, m.d) == (self.a, self.b, self.c, self.d)
+        });
+        let projection = self.u == Fixed2_30(0) && self.v == Fixed2_30(0) && self.w == Fixed2_30(1 << 30);
+        rotation.filter(|_| projection).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::BoxReader;
+    use crate::writer::BoxWriter;
+
+    #[test]
+    fn fixed_raw() {
+        assert_eq!(Fixed16_16::from_f64(1.0).to_raw(), 0x0001_0000);
+        assert_eq!(Fixed16_16::from_f64(-1.5).to_raw(), -0x0001_8000);
+        assert_eq!(UFixed16_16::from_f64(1920.0).to_raw(), 0x0780_0000);
+        assert_eq!(Fixed8_8::from_f64(1.0).to_raw(), 0x0100);
+        assert_eq!(Fixed2_30::from_f64(1.0).to_raw(), 0x4000_0000);
+        assert_eq!(Fixed8_8::from_raw(0x0080).to_f64(), 0.5);
+
+        let mut w = BoxWriter::new();
+        Fixed8_8::from_f64(-0.5).write_to(&mut w).unwrap();
+        let data = w.into_inner();
+        assert_eq!(data, [ 0xff, 0x80 ]);
+        assert_eq!(Fixed8_8::read_from(&mut BoxReader::new(&data)).unwrap().to_f64(), -0.5);
+    }
+
+    #[test]
+    fn rotation() {
+        assert_eq!(Matrix::for_rotation(0), Some(Matrix::default()));
+        assert_eq!(Matrix::for_rotation(45), None);
+        for r in [ 0, 90, 180, 270 ] {
+            assert_eq!(Matrix::for_rotation(r).unwrap().rotation(), Some(r));
+        }
+
+        // 90 degrees, as written by phones.
+        let m = Matrix::for_rotation(90).unwrap();
+        assert_eq!((m.a.to_raw(), m.b.to_raw(), m.c.to_raw(), m.d.to_raw()), (0, 0x10000, -0x10000, 0));
+
+        // Translation is ignored, scaling and projection are not.
+        let m = Matrix { x: Fixed16_16::from_f64(1080.0), ..Matrix::for_rotation(90).unwrap() };
+        assert_eq!(m.rotation(), Some(90));
+        let m = Matrix { a: Fixed16_16::from_f64(2.0), ..Matrix::default() };
+        assert_eq!(m.rotation(), None);
+        let m = Matrix { w: Fixed2_30::from_f64(0.5), ..Matrix::default() };
+        assert_eq!(m.rotation(), None);
+    }
+}
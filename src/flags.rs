@@ -0,0 +1,265 @@
+// Typed flags, for the header of some `FullBox`es, and for samples.
+use std::fmt;
+use std::ops;
+
+use iso_bmff_macros::def_box;
+
+// A newtype around the 24 bits of flags, with a constant for every flag.
+macro_rules! def_flags {
+    (
+        $(#[$doc:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$fdoc:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+        pub struct $name(pub u32);
+
+        impl $name {
+            $(
+                $(#[$fdoc])*
+                pub const $flag: $name = $name($value);
+            )*
+
+            /// Are all the bits of `other` set.
+            pub fn contains(&self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// The flags for the bits as they are in the file.
+            pub fn from_raw(v: u32) -> $name {
+                $name(v)
+            }
+
+            /// The bits as they are in the file.
+            pub fn to_raw(&self) -> u32 {
+                self.0
+            }
+
+            /// Set or clear the bits of `other`.
+            pub fn set(&mut self, other: $name, on: bool) {
+                if on {
+                    self.0 |= other.0;
+                } else {
+                    self.0 &= !other.0;
+                }
+            }
+        }
+
+        impl From<u32> for $name {
+            fn from(v: u32) -> $name {
+                $name(v)
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(v: $name) -> u32 {
+                v.0
+            }
+        }
+
+        impl ops::BitOr for $name {
+            type Output = $name;
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+        }
+
+        impl ops::BitAnd for $name {
+            type Output = $name;
+            fn bitand(self, other: $name) -> $name {
+                $name(self.0 & other.0)
+            }
+        }
+
+        // TrackRunFlags(DATA_OFFSET_PRESENT | 0x1000)
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let mut rest = self.0;
+                let mut names = Vec::new();
+                $(
+                    if $value != 0 && self.0 & $value == $value {
+                        names.push(stringify!($flag).to_string());
+                        rest &= !$value;
+                    }
+                )*
+                if rest != 0 || names.is_empty() {
+                    names.push(format!("{:#x}", rest));
+                }
+                write!(f, "{}({})", stringify!($name), names.join(" | "))
+            }
+        }
+    };
+}
+
+def_flags! {
+    /// The flags of the `tkhd` box.
+    pub struct TrackHeaderFlags {
+        const TRACK_ENABLED = 0x000001;
+        const TRACK_IN_MOVIE = 0x000002;
+        const TRACK_IN_PREVIEW = 0x000004;
+        const TRACK_SIZE_IS_ASPECT_RATIO = 0x000008;
+    }
+}
+
+def_flags! {
+    /// The flags of the `tfhd` box. The flags that say if a field is
+    /// present are set from the fields when the box is written.
+    pub struct TrackFragmentHeaderFlags {
+        const BASE_DATA_OFFSET_PRESENT = 0x000001;
+        const SAMPLE_DESCRIPTION_INDEX_PRESENT = 0x000002;
+        const DEFAULT_SAMPLE_DURATION_PRESENT = 0x000008;
+        const DEFAULT_SAMPLE_SIZE_PRESENT = 0x000010;
+        const DEFAULT_SAMPLE_FLAGS_PRESENT = 0x000020;
+        const DURATION_IS_EMPTY = 0x010000;
+        const DEFAULT_BASE_IS_MOOF = 0x020000;
+    }
+}
+
+def_flags! {
+    /// The flags of the `trun` box. The flags that say if a field is
+    /// present are set from the fields when the box is written.
+    pub struct TrackRunFlags {
+        const DATA_OFFSET_PRESENT = 0x000001;
+        const FIRST_SAMPLE_FLAGS_PRESENT = 0x000004;
+        const SAMPLE_DURATION_PRESENT = 0x000100;
+        const SAMPLE_SIZE_PRESENT = 0x000200;
+        const SAMPLE_FLAGS_PRESENT = 0x000400;
+        const SAMPLE_COMPOSITION_TIME_OFFSETS_PRESENT = 0x000800;
+    }
+}
+
+// The flags of a sample, as in `default_sample_flags` of `trex`.
+def_box! {
+    class SampleFlags {
+        bit(4) reserved = 0;
+        unsigned int(2) is_leading;
+        unsigned int(2) sample_depends_on;
+        unsigned int(2) sample_is_depended_on;
+        unsigned int(2) sample_has_redundancy;
+        bit(3) sample_padding_value;
+        bit(1) sample_is_non_sync_sample;
+        unsigned int(16) sample_degradation_priority;
+    }
+}
+
+impl SampleFlags {
+    /// A sync sample, like a keyframe: it does not depend on other samples.
+    pub fn sync() -> SampleFlags {
+        SampleFlags {
+            sample_depends_on: 2,
+            ..SampleFlags::default()
+        }
+    }
+
+    /// A sample that depends on other samples.
+    pub fn non_sync() -> SampleFlags {
+        SampleFlags {
+            sample_depends_on: 1,
+            sample_is_non_sync_sample: true,
+            ..SampleFlags::default()
+        }
+    }
+
+    /// Is this a sync sample.
+    pub fn is_sync(&self) -> bool {
+        !self.sample_is_non_sync_sample
+    }
+
+    /// Does this sample depend on other samples, if that is known.
+    pub fn depends_on_others(&self) -> Option<bool> {
+        match self.sample_depends_on {
+            1 => Some(true),
+            2 => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Do other samples depend on this one, if that is known.
+    pub fn is_depended_on(&self) -> Option<bool> {
+        match self.sample_is_depended_on {
+            1 => Some(true),
+            2 => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl From<u32> for SampleFlags {
+    fn from(v: u32) -> SampleFlags {
+        SampleFlags {
+            is_leading:                     ((v >> 26) & 0x3) as u8,
+            sample_depends_on:              ((v >> 24) & 0x3) as u8,
+            sample_is_depended_on:          ((v >> 22) & 0x3) as u8,
+            sample_has_redundancy:          ((v >> 20) & 0x3) as u8,
+            sample_padding_value:           ((v >> 17) & 0x7) as u8,
+            sample_is_non_sync_sample:      (v >> 16) & 0x1 != 0,
+            sample_degradation_priority:    v as u16,
+        }
+    }
+}
+
+impl From<SampleFlags> for u32 {
+    fn from(f: SampleFlags) -> u32 {
+        ((f.is_leading as u32 & 0x3) << 26) |
+            ((f.sample_depends_on as u32 & 0x3) << 24) |
+            ((f.sample_is_depended_on as u32 & 0x3) << 22) |
+            ((f.sample_has_redundancy as u32 & 0x3) << 20) |
+            ((f.sample_padding_value as u32 & 0x7) << 17) |
+            ((f.sample_is_non_sync_sample as u32) << 16) |
+            f.sample_degradation_priority as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::{BoxReader, ReadFrom};
+    use crate::writer::{BoxWriter, WriteTo};
+
+    #[test]
+    fn sample_flags_u32() {
+        // is_leading 1, depends_on 2, is_depended_on 1, redundancy 2,
+        // padding 5, non-sync, priority 0x1234.
+        let v = 0x066b_1234;
+        let f = SampleFlags::from(v);
+        assert_eq!(
+            (f.is_leading, f.sample_depends_on, f.sample_is_depended_on, f.sample_has_redundancy),
+            (1, 2, 1, 2),
+        );
+        assert_eq!(f.sample_padding_value, 5);
+        assert!(f.sample_is_non_sync_sample);
+        assert_eq!(f.sample_degradation_priority, 0x1234);
+        assert_eq!(u32::from(f.clone()), v);
+
+        // The same as in a box.
+        let mut w = BoxWriter::new();
+        f.write_to(&mut w).unwrap();
+        let data = w.into_inner();
+        assert_eq!(data, v.to_be_bytes());
+        assert_eq!(SampleFlags::read_from(&mut BoxReader::new(&data)).unwrap(), f);
+
+        assert_eq!(u32::from(SampleFlags::sync()), 0x0200_0000);
+        assert_eq!(u32::from(SampleFlags::non_sync()), 0x0101_0000);
+        assert!(!SampleFlags::from(0x0101_0000).is_sync());
+    }
+
+    #[test]
+    fn flags_raw() {
+        let mut f = TrackRunFlags::from_raw(0x000401);
+        assert!(f.contains(TrackRunFlags::DATA_OFFSET_PRESENT));
+        f.set(TrackRunFlags::DATA_OFFSET_PRESENT, false);
+        assert_eq!(u32::from(f), 0x000400);
+        assert_eq!(format!("{:?}", TrackRunFlags::from_raw(0x001400)), "TrackRunFlags(SAMPLE_FLAGS_PRESENT | 0x1000)");
+    }
+}
@@ -0,0 +1,203 @@
+// Language codes of media.
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use iso_bmff_macros::def_box;
+
+use crate::node::BoxNode;
+use crate::reader::invalid_data;
+use crate::spec::{ExtendedLanguageBox, MediaBox};
+
+// ISO-639-1 two letter codes and their ISO-639-2/T three letter codes,
+// sorted by the two letter code.
+const ISO_639_1: &[(&str, &str)] = &[
+    ("aa", "aar"), ("ab", "abk"), ("ae", "ave"), ("af", "afr"), ("ak", "aka"),
+    ("am", "amh"), ("an", "arg"), ("ar", "ara"), ("as", "asm"), ("av", "ava"),
+    ("ay", "aym"), ("az", "aze"), ("ba", "bak"), ("be", "bel"), ("bg", "bul"),
+    ("bh", "bih"), ("bi", "bis"), ("bm", "bam"), ("bn", "ben"), ("bo", "bod"),
+    ("br", "bre"), ("bs", "bos"), ("ca", "cat"), ("ce", "che"), ("ch", "cha"),
+    ("co", "cos"), ("cr", "cre"), ("cs", "ces"), ("cu", "chu"), ("cv", "chv"),
+    ("cy", "cym"), ("da", "dan"), ("de", "deu"), ("dv", "div"), ("dz", "dzo"),
+    ("ee", "ewe"), ("el", "ell"), ("en", "eng"), ("eo", "epo"), ("es", "spa"),
+    ("et", "est"), ("eu", "eus"), ("fa", "fas"), ("ff", "ful"), ("fi", "fin"),
+    ("fj", "fij"), ("fo", "fao"), ("fr", "fra"), ("fy", "fry"), ("ga", "gle"),
+    ("gd", "gla"), ("gl", "glg"), ("gn", "grn"), ("gu", "guj"), ("gv", "glv"),
+    ("ha", "hau"), ("he", "heb"), ("hi", "hin"), ("ho", "hmo"), ("hr", "hrv"),
+    ("ht", "hat"), ("hu", "hun"), ("hy", "hye"), ("hz", "her"), ("ia", "ina"),
+    ("id", "ind"), ("ie", "ile"), ("ig", "ibo"), ("ii", "iii"), ("ik", "ipk"),
+    ("io", "ido"), ("is", "isl"), ("it", "ita"), ("iu", "iku"), ("ja", "jpn"),
+    ("jv", "jav"), ("ka", "kat"), ("kg", "kon"), ("ki", "kik"), ("kj", "kua"),
+    ("kk", "kaz"), ("kl", "kal"), ("km", "khm"), ("kn", "kan"), ("ko", "kor"),
+    ("kr", "kau"), ("ks", "kas"), ("ku", "kur"), ("kv", "kom"), ("kw", "cor"),
+    ("ky", "kir"), ("la", "lat"), ("lb", "ltz"), ("lg", "lug"), ("li", "lim"),
+    ("ln", "lin"), ("lo", "lao"), ("lt", "lit"), ("lu", "lub"), ("lv", "lav"),
+    ("mg", "mlg"), ("mh", "mah"), ("mi", "mri"), ("mk", "mkd"), ("ml", "mal"),
+    ("mn", "mon"), ("mr", "mar"), ("ms", "msa"), ("mt", "mlt"), ("my", "mya"),
+    ("na", "nau"), ("nb", "nob"), ("nd", "nde"), ("ne", "nep"), ("ng", "ndo"),
+    ("nl", "nld"), ("nn", "nno"), ("no", "nor"), ("nr", "nbl"), ("nv", "nav"),
+    ("ny", "nya"), ("oc", "oci"), ("oj", "oji"), ("om", "orm"), ("or", "ori"),
+    ("os", "oss"), ("pa", "pan"), ("pi", "pli"), ("pl", "pol"), ("ps", "pus"),
+    ("pt", "por"), ("qu", "que"), ("rm", "roh"), ("rn", "run"), ("ro", "ron"),
+    ("ru", "rus"), ("rw", "kin"), ("sa", "san"), ("sc", "srd"), ("sd", "snd"),
+    ("se", "sme"), ("sg", "sag"), ("si", "sin"), ("sk", "slk"), ("sl", "slv"),
+    ("sm", "smo"), ("sn", "sna"), ("so", "som"), ("sq", "sqi"), ("sr", "srp"),
+    ("ss", "ssw"), ("st", "sot"), ("su", "sun"), ("sv", "swe"), ("sw", "swa"),
+    ("ta", "tam"), ("te", "tel"), ("tg", "tgk"), ("th", "tha"), ("ti", "tir"),
+    ("tk", "tuk"), ("tl", "tgl"), ("tn", "tsn"), ("to", "ton"), ("tr", "tur"),
+    ("ts", "tso"), ("tt", "tat"), ("tw", "twi"), ("ty", "tah"), ("ug", "uig"),
+    ("uk", "ukr"), ("ur", "urd"), ("uz", "uzb"), ("ve", "ven"), ("vi", "vie"),
+    ("vo", "vol"), ("wa", "wln"), ("wo", "wol"), ("xh", "xho"), ("yi", "yid"),
+    ("yo", "yor"), ("za", "zha"), ("zh", "zho"), ("zu", "zul"),
+];
+
+// An ISO-639-2/T language code, like "eng", as three 5-bit letters.
+// The default is "und", undetermined.
+def_box! {
+    class Language {
+        unsigned int(5)[3] code = { 0x15, 0x0e, 0x04 };
+    }
+}
+
+impl Language {
+    /// The language for a three letter code, like "eng".
+    pub fn new(code: &str) -> Option<Language> {
+        let b = code.as_bytes();
+        if b.len() != 3 || !b.iter().all(|c| c.is_ascii_lowercase()) {
+            return None;
+        }
+        Some(Language {
+            code: [ b[0] - 0x60, b[1] - 0x60, b[2] - 0x60 ],
+        })
+    }
+
+    /// The language for a BCP-47 tag, like "en-US", as in the `elng` box.
+    ///
+    /// The language of the tag can be a two letter ISO-639-1 code or a
+    /// three letter code. For other tags this returns `None`.
+    pub fn from_tag(tag: &str) -> Option<Language> {
+        let primary = tag.split('-').next().unwrap_or("").to_ascii_lowercase();
+        if primary.len() == 2 {
+            let i = ISO_639_1.binary_search_by_key(&primary.as_str(), |&(a, _)| a).ok()?;
+            return Language::new(ISO_639_1[i].1);
+        }
+        Language::new(&primary)
+    }
+
+    /// Is this "und", undetermined.
+    pub fn is_undetermined(&self) -> bool {
+        *self == Language::default()
+    }
+}
+
+impl FromStr for Language {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Language> {
+        Language::new(s).ok_or_else(|| invalid_data(format!("invalid language code {:?}", s)))
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &c in &self.code {
+            write!(f, "{}", (c + 0x60) as char)?;
+        }
+        Ok(())
+    }
+}
+
+impl MediaBox {
+    /// The language of the media, as a BCP-47 tag. This is the tag of
+    /// the `elng` box if there is one, otherwise the language code of the
+    /// `mdhd` box. `None` if the language is not known.
+    pub fn language(&self) -> Option<String> {
+        let mut lang = None;
+        for b in &self.boxes {
+            match b {
+                BoxNode::ExtendedLanguageBox(elng) if !elng.extended_language.is_empty() => {
+                    return Some(elng.extended_language.to_string());
+                },
+                BoxNode::MediaHeaderBox(mdhd) if !mdhd.language.is_undetermined() => {
+                    lang = Some(mdhd.language.to_string());
+                },
+                _ => {},
+            }
+        }
+        lang
+    }
+
+    /// Set the language of the media from a BCP-47 tag. The `mdhd` box
+    /// gets the language code, or "und" if the tag does not have one.
+    /// The `elng` box is added if the tag says more than the code does.
+    pub fn set_language(&mut self, tag: &str) {
+        let lang = Language::from_tag(tag);
+        // "en" and "eng" say no more than the code does, "en-US" does.
+        let need_elng = lang.is_none() || tag.contains('-');
+        let lang = lang.unwrap_or_default();
+        for b in self.boxes.iter_mut() {
+            if let BoxNode::MediaHeaderBox(mdhd) = b {
+                mdhd.language = lang.clone();
+            }
+        }
+        self.boxes.retain(|b| !matches!(b, BoxNode::ExtendedLanguageBox(_)));
+        if need_elng {
+            // elng comes right after mdhd.
+            let pos = self.boxes.iter().position(|b| matches!(b, BoxNode::MediaHeaderBox(_)));
+            let elng = BoxNode::ExtendedLanguageBox(ExtendedLanguageBox {
+                extended_language: tag.into(),
+                ..Default::default()
+            });
+            self.boxes.insert(pos.map(|p| p + 1).unwrap_or(0), elng);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::MediaHeaderBox;
+
+    #[test]
+    fn from_tag() {
+        let eng = Language::new("eng").unwrap();
+        assert_eq!(Language::from_tag("en"), Some(eng.clone()));
+        assert_eq!(Language::from_tag("eng"), Some(eng.clone()));
+        assert_eq!(Language::from_tag("EN-us"), Some(eng));
+        assert_eq!(Language::from_tag("zz"), None);
+        assert_eq!(Language::from_tag("i-klingon"), None);
+        assert_eq!(Language::default().to_string(), "und");
+        assert!("En".parse::<Language>().is_err());
+    }
+
+    #[test]
+    fn elng_priority() {
+        let mut mdia = MediaBox {
+            boxes: vec![ BoxNode::MediaHeaderBox(MediaHeaderBox::default()) ],
+            ..Default::default()
+        };
+        assert_eq!(mdia.language(), None);
+
+        mdia.set_language("en");
+        assert_eq!(mdia.boxes.len(), 1);
+        assert_eq!(mdia.language().as_deref(), Some("eng"));
+
+        // The elng box wins over the mdhd box.
+        mdia.set_language("en-US");
+        assert!(matches!(mdia.boxes[1], BoxNode::ExtendedLanguageBox(_)));
+        assert_eq!(mdia.language().as_deref(), Some("en-US"));
+        match &mdia.boxes[0] {
+            BoxNode::MediaHeaderBox(mdhd) => assert_eq!(mdhd.language.to_string(), "eng"),
+            other => panic!("mdhd: {:?}", other),
+        }
+
+        // A tag without an ISO-639 language only goes in the elng box.
+        mdia.set_language("x-private");
+        assert_eq!(mdia.boxes.len(), 2);
+        assert_eq!(mdia.language().as_deref(), Some("x-private"));
+
+        mdia.set_language("fr");
+        assert_eq!(mdia.boxes.len(), 1);
+        assert_eq!(mdia.language().as_deref(), Some("fra"));
+    }
+}
@@ -1,5 +1,33 @@
+// The code generated by def_box! refers to ::iso_bmff, also in this crate.
+extern crate self as iso_bmff;
 
+mod context;
+mod drc;
+mod fixed;
+mod flags;
+mod language;
+mod node;
+mod piff;
+mod reader;
+mod registry;
 mod spec;
+mod time;
+mod types;
+mod writer;
 
+pub use context::*;
+pub use drc::*;
+pub use fixed::*;
+pub use flags::*;
+pub use language::*;
+pub use node::*;
+pub use piff::*;
+pub use reader::*;
+pub use registry::*;
 pub use spec::*;
+pub use time::*;
+pub use types::*;
+pub use writer::*;
 
+// So that other crates can define their own boxes, see `register_box`.
+pub use iso_bmff_macros::def_box;
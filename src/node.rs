@@ -0,0 +1,248 @@
+// A tree of boxes.
+use std::io;
+
+use crate::reader::{BoxReader, ReadFrom};
+use crate::registry::{self, UserBox};
+use crate::writer::{BoxWriter, WriteTo};
+use crate::types::{FourCC, RawBox, SizeForm, Uuid};
+use crate::drc::*;
+use crate::piff::*;
+use crate::spec::*;
+
+/// Information about a box type with a fixed fourcc.
+///
+/// This is implemented by `def_box!` for every box that has one.
+pub trait BoxInfo {
+    const FOURCC: FourCC;
+    /// The extended type, for a `uuid` box.
+    const USERTYPE: Option<Uuid> = None;
+
+    /// The child boxes, if this is a container box.
+    fn children(&self) -> Option<&[BoxNode]> {
+        None
+    }
+}
+
+// The fourcc of a box in a `BoxNode`: the one of its type, or the
+// one in a field, for a box like `FreeSpaceBox(free_type)`.
+macro_rules! node_fourcc {
+    ($name:ident, $b:ident) => { <$name as BoxInfo>::FOURCC };
+    ($name:ident, $b:ident, $field:ident) => { FourCC($b.$field) };
+}
+
+// The child boxes of a box in a `BoxNode`. A box with a fourcc
+// that is a variable is not a container.
+macro_rules! node_children {
+    ($b:ident) => { $b.children() };
+    ($b:ident, $field:ident) => { None };
+}
+
+// The boxes come from `box_index!`, which lists the classes in
+// the `def_box!` invocations, see iso-bmff-macros/src/index.rs.
+macro_rules! box_node {
+    ($($name:ident $(($field:ident))?,)*) => {
+        /// Any box.
+        ///
+        /// Boxes that are not known, or that could not be decoded,
+        /// are kept as `Unknown`, so that they can be written back as-is.
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum BoxNode {
+            $(
+                $name($name),
+            )*
+            /// A box of a type that was registered with `register_box`.
+            User(UserBox),
+            /// A box that is not known.
+            Unknown {
+                fourcc: FourCC,
+                bytes:  Vec<u8>,
+                size_form: SizeForm,
+            },
+        }
+
+        impl Default for BoxNode {
+            /// An empty `Unknown` box.
+            fn default() -> BoxNode {
+                BoxNode::Unknown {
+                    fourcc: FourCC::default(),
+                    bytes:  Vec::new(),
+                    size_form: SizeForm::default(),
+                }
+            }
+        }
+
+        impl BoxNode {
+            /// The fourcc of this box.
+            #[allow(unused_variables)]
+            pub fn fourcc(&self) -> FourCC {
+                match self {
+                    $(
+                        &BoxNode::$name(ref b) => node_fourcc!($name, b $(, $field)?),
+                    )*
+                    &BoxNode::User(ref b) => b.fourcc(),
+                    &BoxNode::Unknown{ fourcc, .. } => fourcc,
+                }
+            }
+
+            /// The child boxes, if this is a container box.
+            #[allow(unused_variables)]
+            pub fn children(&self) -> Option<&[BoxNode]> {
+                match self {
+                    $(
+                        &BoxNode::$name(ref b) => node_children!(b $(, $field)?),
+                    )*
+                    &BoxNode::User(ref b) => b.children(),
+                    &BoxNode::Unknown{ .. } => None,
+                }
+            }
+        }
+
+        impl BoxNode {
+            fn read_node(r: &mut BoxReader) -> io::Result<BoxNode> {
+                let fourcc = r.peek_header()?.fourcc;
+                let usertype = r.peek_usertype()?;
+                if let Some(b) = registry::read_registered(r, fourcc, usertype) {
+                    return Ok(BoxNode::User(b));
+                }
+                $(
+                    if <$name as ReadFrom>::is_next(r) {
+                        let mut r2 = r.clone();
+                        if let Ok(b) = $name::read_from(&mut r2) {
+                            *r = r2;
+                            return Ok(BoxNode::$name(b));
+                        }
+                    }
+                )*
+                let raw = RawBox::read_from(r)?;
+                Ok(BoxNode::Unknown {
+                    fourcc: raw.fourcc,
+                    bytes:  raw.data,
+                    size_form: raw.size_form,
+                })
+            }
+        }
+
+        impl WriteTo for BoxNode {
+            fn write_to(&self, w: &mut BoxWriter) -> io::Result<()> {
+                match self {
+                    $(
+                        &BoxNode::$name(ref b) => b.write_to(w)?,
+                    )*
+                    &BoxNode::User(ref b) => b.write_to(w)?,
+                    &BoxNode::Unknown{ fourcc, ref bytes, size_form } => {
+                        let start = w.begin_box_sized(fourcc, size_form)?;
+                        w.write(bytes)?;
+                        w.end_box(start)?;
+                    },
+                }
+                // As in `read_from`, for the boxes after the `hdlr` box.
+                if let BoxNode::HandlerBox(ref h) = *self {
+                    w.set_var("handler_type", h.handler_type as i64);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+macro_rules! sample_group_entry {
+    ($($name:ident,)*) => {
+        /// An entry of a `SampleGroupDescriptionBox`.
+        ///
+        /// The class of the entry follows from the `grouping_type`, and
+        /// the handler of the track. Entries that are not known, or that
+        /// could not be decoded, are kept as `Unknown`.
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum SampleGroupEntry {
+            $(
+                $name($name),
+            )*
+            /// An entry that is not known.
+            Unknown(Vec<u8>),
+        }
+
+        impl Default for SampleGroupEntry {
+            fn default() -> SampleGroupEntry {
+                SampleGroupEntry::Unknown(Vec::new())
+            }
+        }
+
+        impl SampleGroupEntry {
+            // Read an entry. If `whole`, it must be all of `r`.
+            fn read_entry(r: &mut BoxReader, whole: bool) -> io::Result<SampleGroupEntry> {
+                $(
+                    if <$name as ReadFrom>::is_next(r) {
+                        let mut r2 = r.clone();
+                        if let Ok(e) = $name::read_from(&mut r2) {
+                            if !whole || r2.left_bits() == 0 {
+                                *r = r2;
+                                return Ok(SampleGroupEntry::$name(e));
+                            }
+                        }
+                    }
+                )*
+                Ok(SampleGroupEntry::Unknown(r.read(r.left())?.to_vec()))
+            }
+        }
+
+        impl ReadFrom for SampleGroupEntry {
+            fn read_from(r: &mut BoxReader) -> io::Result<SampleGroupEntry> {
+                // In version 1 of the `sgpd` box the length of the
+                // entries is known, and an entry is read from just that.
+                let len = match r.var("default_length") {
+                    Some(0) => r.var("description_length"),
+                    len => len,
+                };
+                match len {
+                    Some(len) => SampleGroupEntry::read_entry(&mut r.read_part(len as usize)?, true),
+                    None => SampleGroupEntry::read_entry(r, false),
+                }
+            }
+        }
+
+        impl WriteTo for SampleGroupEntry {
+            fn write_to(&self, w: &mut BoxWriter) -> io::Result<()> {
+                match self {
+                    $(
+                        &SampleGroupEntry::$name(ref e) => e.write_to(w),
+                    )*
+                    &SampleGroupEntry::Unknown(ref bytes) => w.write(bytes),
+                }
+            }
+        }
+    }
+}
+
+iso_bmff_macros::box_index!(box_node, sample_group_entry,
+    "src/spec.rs", "src/piff.rs", "src/drc.rs", "src/fixed.rs", "src/flags.rs", "src/language.rs");
+
+impl ReadFrom for BoxNode {
+    fn read_from(r: &mut BoxReader) -> io::Result<BoxNode> {
+        let node = BoxNode::read_node(r)?;
+        // The handler type is needed to decode the boxes after the
+        // `hdlr` box, like `SampleDescriptionBox(handler_type)`.
+        if let BoxNode::HandlerBox(ref h) = node {
+            r.set_var("handler_type", h.handler_type as i64);
+        }
+        Ok(node)
+    }
+}
+
+/// Read all boxes in a buffer, like an entire mp4 file.
+pub fn read_boxes(data: &[u8]) -> io::Result<Vec<BoxNode>> {
+    let mut r = BoxReader::new(data);
+    let mut boxes = Vec::new();
+    while r.left() > 0 {
+        boxes.push(BoxNode::read_from(&mut r)?);
+    }
+    Ok(boxes)
+}
+
+/// Write a list of boxes.
+pub fn write_boxes(boxes: &[BoxNode]) -> io::Result<Vec<u8>> {
+    let mut w = BoxWriter::new();
+    for b in boxes {
+        b.write_to(&mut w)?;
+    }
+    Ok(w.into_inner())
+}
@@ -0,0 +1,118 @@
+// Boxes from the Protected Interoperable File Format (PIFF) 1.1,
+// as used by Smooth Streaming. These are all `uuid` boxes.
+use iso_bmff_macros::def_box;
+
+// Track Fragment Extended Header ("tfxd")
+def_box! {
+    aligned(8) class PiffTrackFragmentDurationBox
+       extends FullBox("uuid", extended_type = "6d1d9b05-42d5-44e6-80e2-141daff757b2", version, 0) {
+       if (version == 1) {
+          unsigned int(64) fragment_absolute_time;
+          unsigned int(64) fragment_duration;
+       } else {
+          unsigned int(32) fragment_absolute_time;
+          unsigned int(32) fragment_duration;
+       }
+    }
+}
+
+// Track Fragment Reference ("tfrf")
+def_box! {
+    aligned(8) class PiffTrackFragmentReferenceBox
+       extends FullBox("uuid", extended_type = "d4807ef2-ca39-4695-8e54-26cb9e46a79f", version, 0) {
+       unsigned int(8) fragment_count;
+       for (i = 0; i < fragment_count; i++) {
+          if (version == 1) {
+             unsigned int(64) fragment_absolute_time;
+             unsigned int(64) fragment_duration;
+          } else {
+             unsigned int(32) fragment_absolute_time;
+             unsigned int(32) fragment_duration;
+          }
+       }
+    }
+}
+
+// Sample Encryption Box
+def_box! {
+    aligned(8) class PiffSampleEncryptionBox
+       extends FullBox("uuid", extended_type = "a2394f52-5a9b-4f14-a244-6c427c648df4", version = 0, flags) {
+       if (flags & 1) {
+          unsigned int(24) AlgorithmID;
+          unsigned int(8) IV_size;
+          unsigned int(8)[16] KID;
+       }
+       unsigned int(32) sample_count;
+       // The size of the initialization vectors is IV_size, or if that is
+       // not present, the one in the track encryption box. So the samples
+       // are kept as-is.
+       unsigned int(8) sample_data[];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{read_boxes, write_boxes, BoxNode};
+
+    fn uuid_box(uuid: &str, body: &[u8]) -> Vec<u8> {
+        let mut v = ((body.len() + 24) as u32).to_be_bytes().to_vec();
+        v.extend_from_slice(b"uuid");
+        let hex: String = uuid.chars().filter(|c| *c != '-').collect();
+        v.extend((0 .. 16).map(|i| u8::from_str_radix(&hex[i * 2 .. i * 2 + 2], 16).unwrap()));
+        v.extend_from_slice(body);
+        v
+    }
+
+    fn roundtrip(data: &[u8]) -> BoxNode {
+        let boxes = read_boxes(data).unwrap();
+        assert_eq!(write_boxes(&boxes).unwrap(), data);
+        boxes.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn tfxd() {
+        let mut body = vec![ 1, 0, 0, 0 ];
+        body.extend_from_slice(&10_000_000u64.to_be_bytes());
+        body.extend_from_slice(&20_000_000u64.to_be_bytes());
+        match roundtrip(&uuid_box("6d1d9b05-42d5-44e6-80e2-141daff757b2", &body)) {
+            BoxNode::PiffTrackFragmentDurationBox(b) => {
+                assert_eq!(b.fragment_absolute_time, 10_000_000);
+                assert_eq!(b.fragment_duration, 20_000_000);
+            },
+            other => panic!("tfxd: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tfrf() {
+        let mut body = vec![ 0, 0, 0, 0, 2 ];
+        for v in [ 100u32, 10, 110, 10 ] {
+            body.extend_from_slice(&v.to_be_bytes());
+        }
+        match roundtrip(&uuid_box("d4807ef2-ca39-4695-8e54-26cb9e46a79f", &body)) {
+            BoxNode::PiffTrackFragmentReferenceBox(b) => assert_eq!(b.fragments.len(), 2),
+            other => panic!("tfrf: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn senc() {
+        let body = [ 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 3, 4, 5, 6, 7, 8 ];
+        match roundtrip(&uuid_box("a2394f52-5a9b-4f14-a244-6c427c648df4", &body)) {
+            BoxNode::PiffSampleEncryptionBox(b) => {
+                assert_eq!(b.sample_count, 1);
+                assert_eq!(b.sample_data, [ 1, 2, 3, 4, 5, 6, 7, 8 ]);
+            },
+            other => panic!("senc: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_uuid() {
+        let data = uuid_box("00000000-0000-0000-0000-000000000001", &[ 1, 2, 3 ]);
+        match roundtrip(&data) {
+            BoxNode::UuidBox(b) => assert_eq!(b.data, [ 1, 2, 3 ]),
+            other => panic!("uuid: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,410 @@
+// Reading boxes from a buffer.
+use std::io;
+
+use crate::context::Context;
+use crate::types::{BoxString, FixedString, FourCC, RawBox, SizeForm, StringEncoding, Uuid};
+
+/// Types that can be read from a `BoxReader`.
+///
+/// This is implemented by `def_box!` for every class.
+pub trait ReadFrom: Sized {
+    fn read_from(r: &mut BoxReader) -> io::Result<Self>;
+
+    /// Is one of these next, for a field that is `# optional` at the end
+    /// of a box. That is if there is data left, and for a box with a
+    /// fixed type, if the next box has that type.
+    fn is_next(r: &BoxReader) -> bool {
+        r.left() > 0
+    }
+}
+
+/// The header of a box.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BoxHeader {
+    pub fourcc:     FourCC,
+    /// Size of the entire box, including the header.
+    pub size:       u64,
+    /// Size of the header, 8 or 16 bytes.
+    pub header_size: u32,
+    /// How the size is written.
+    pub size_form:  SizeForm,
+}
+
+/// Reads boxes, and the fields inside them, from a buffer.
+///
+/// Fields do not have to be a multiple of 8 bits, they are
+/// packed most significant bit first.
+#[derive(Clone, Debug)]
+pub struct BoxReader<'a> {
+    data:   &'a [u8],
+    pos:    usize,
+    // bits already read from data[pos].
+    bitpos: u32,
+    // arguments for the next class that is read.
+    args:   Vec<i64>,
+    // values known from enclosing boxes, like `handler_type`.
+    ctx:    Context,
+    // the box that this is the body of.
+    parent: FourCC,
+}
+
+pub(crate) fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl<'a> BoxReader<'a> {
+    /// New reader for a buffer.
+    pub fn new(data: &'a [u8]) -> BoxReader<'a> {
+        BoxReader {
+            data,
+            pos:    0,
+            bitpos: 0,
+            args:   Vec::new(),
+            ctx:    Context::new(),
+            parent: FourCC::default(),
+        }
+    }
+
+    /// Number of (whole) bytes left.
+    pub fn left(&self) -> usize {
+        ((self.data.len() - self.pos) * 8 - self.bitpos as usize) / 8
+    }
+
+    /// Number of bits left.
+    pub fn left_bits(&self) -> u64 {
+        (self.data.len() - self.pos) as u64 * 8 - self.bitpos as u64
+    }
+
+    /// Check that we are at a multiple of `bits` bits.
+    pub fn check_aligned(&self, bits: u32) -> io::Result<()> {
+        let bitpos = self.pos as u64 * 8 + self.bitpos as u64;
+        if bits > 0 && !bitpos.is_multiple_of(bits as u64) {
+            return Err(invalid_data(format!("position is not aligned({})", bits)));
+        }
+        Ok(())
+    }
+
+    /// Check that what is left of the box is a whole number of
+    /// elements of `bits` bits, for an array that runs to the end.
+    pub fn check_elements(&self, name: &str, bits: u32) -> io::Result<()> {
+        let left = self.left_bits();
+        if bits > 0 && !left.is_multiple_of(bits as u64) {
+            return Err(invalid_data(format!("{}: {} bits at the end of the box are not a whole number of elements", name, left)));
+        }
+        Ok(())
+    }
+
+    /// Current position.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Read a number of bytes.
+    pub fn read(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        self.check_aligned(8)?;
+        if len > self.left() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let data = &self.data[self.pos .. self.pos + len];
+        self.pos += len;
+        Ok(data)
+    }
+
+    /// Read an unsigned integer of `bits` bits.
+    pub fn read_uint(&mut self, bits: u32) -> io::Result<u64> {
+        if bits > 64 {
+            return Err(io::Error::other(format!("cannot read int({})", bits)));
+        }
+        if self.bitpos == 0 && bits.is_multiple_of(8) {
+            let mut v = 0u64;
+            for &b in self.read((bits / 8) as usize)? {
+                v = (v << 8) | (b as u64);
+            }
+            return Ok(v);
+        }
+        if bits as u64 > self.left_bits() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut v = 0u64;
+        let mut todo = bits;
+        while todo > 0 {
+            let avail = 8 - self.bitpos;
+            let n = std::cmp::min(avail, todo);
+            let b = (self.data[self.pos] as u64 >> (avail - n)) & ((1 << n) - 1);
+            v = (v << n) | b;
+            todo -= n;
+            self.bitpos += n;
+            if self.bitpos == 8 {
+                self.bitpos = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(v)
+    }
+
+    /// Read a signed integer of `bits` bits.
+    pub fn read_int(&mut self, bits: u32) -> io::Result<i64> {
+        let v = self.read_uint(bits)?;
+        if bits == 0 || bits == 64 {
+            return Ok(v as i64);
+        }
+        // sign-extend.
+        let shift = 64 - bits;
+        Ok(((v << shift) as i64) >> shift)
+    }
+
+    /// Read a null-terminated string: UTF-8, or UTF-16 if it starts with
+    /// a byte order mark. A string without a null at the end of the box
+    /// is accepted as well, many files have those.
+    pub fn read_string(&mut self) -> io::Result<BoxString> {
+        self.check_aligned(8)?;
+        let data = &self.data[self.pos..];
+        let encoding = match data {
+            [ 0xfe, 0xff, .. ] => StringEncoding::Utf16BE,
+            [ 0xff, 0xfe, .. ] => StringEncoding::Utf16LE,
+            _ => StringEncoding::Utf8,
+        };
+        let (value, len, unterminated) = if encoding == StringEncoding::Utf8 {
+            let end = data.iter().position(|&b| b == 0);
+            let len = end.unwrap_or(data.len());
+            let s = String::from_utf8(data[..len].to_vec()).map_err(|_| invalid_data("string is not UTF-8"))?;
+            (s, len + end.map(|_| 1).unwrap_or(0), end.is_none())
+        } else {
+            let mut units = Vec::new();
+            let mut len = 2;
+            let mut unterminated = true;
+            while len < data.len() {
+                if data.len() - len < 2 {
+                    return Err(invalid_data("UTF-16 string has an odd number of bytes"));
+                }
+                let b = [ data[len], data[len + 1] ];
+                len += 2;
+                let u = if encoding == StringEncoding::Utf16BE { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) };
+                if u == 0 {
+                    unterminated = false;
+                    break;
+                }
+                units.push(u);
+            }
+            let s = String::from_utf16(&units).map_err(|_| invalid_data("string is not UTF-16"))?;
+            (s, len, unterminated)
+        };
+        self.pos += len;
+        Ok(BoxString { value, encoding, unterminated })
+    }
+
+    /// Read a string in a field of a fixed size, where the first byte
+    /// is the length of the string (like `compressorname`).
+    pub fn read_fixed_string(&mut self, size: usize) -> io::Result<FixedString> {
+        Ok(FixedString(self.read(size)?.to_vec()))
+    }
+
+    /// Read the header of a box.
+    pub fn read_header(&mut self) -> io::Result<BoxHeader> {
+        let start = self.pos;
+        let size = self.read_uint(32)?;
+        let fourcc = FourCC(self.read_uint(32)? as u32);
+        let (size, size_form) = match size {
+            // box extends to the end of the data.
+            0 => ((self.left() + 8) as u64, SizeForm::ToEnd),
+            1 => (self.read_uint(64)?, SizeForm::Large),
+            size => (size, SizeForm::Normal),
+        };
+        let header_size = (self.pos - start) as u32;
+        if size < header_size as u64 || size - header_size as u64 > self.left() as u64 {
+            return Err(invalid_data(format!("{}: invalid box size {}", fourcc, size)));
+        }
+        Ok(BoxHeader {
+            fourcc,
+            size,
+            header_size,
+            size_form,
+        })
+    }
+
+    /// Look at the header of the next box, without reading it.
+    pub fn peek_header(&self) -> io::Result<BoxHeader> {
+        self.clone().read_header()
+    }
+
+    /// Is the rest of the data a run of boxes. For an `# optional`
+    /// field before the child boxes at the end of a box, like
+    /// `schema_location` of an `XMLMetaDataSampleEntry`.
+    pub fn is_boxes(&self) -> bool {
+        let mut r = self.clone();
+        while r.left() > 0 {
+            let header = match r.read_header() {
+                Ok(header) => header,
+                Err(_) => return false,
+            };
+            let fourcc = header.fourcc.0.to_be_bytes();
+            if !fourcc.iter().all(|&b| b == b' ' || b.is_ascii_graphic()) {
+                return false;
+            }
+            let _ = r.read((header.size - header.header_size as u64) as usize);
+        }
+        self.left() > 0
+    }
+
+    /// Read the header of a box. Returns the header,
+    /// and a reader for the rest of the box.
+    pub fn read_box(&mut self) -> io::Result<(BoxHeader, BoxReader<'a>)> {
+        let header = self.read_header()?;
+        let data = self.read((header.size - header.header_size as u64) as usize)?;
+        let mut body = BoxReader::new(data);
+        body.ctx = self.ctx.clone();
+        body.parent = header.fourcc;
+        Ok((header, body))
+    }
+
+    /// Read `len` bytes, as a reader for a part of a box with a known
+    /// length, like an entry of a `sgpd` box.
+    pub fn read_part(&mut self, len: usize) -> io::Result<BoxReader<'a>> {
+        let data = self.read(len)?;
+        let mut part = BoxReader::new(data);
+        part.ctx = self.ctx.clone();
+        part.parent = self.parent;
+        Ok(part)
+    }
+
+    /// The fourcc of the box that is being read, or 0 at the top level.
+    pub fn parent(&self) -> FourCC {
+        self.parent
+    }
+
+    /// Check the type of a box.
+    pub fn expect_fourcc(&self, header: &BoxHeader, fourcc: FourCC) -> io::Result<()> {
+        if header.fourcc != fourcc {
+            return Err(invalid_data(format!("expected box {}, got {}", fourcc, header.fourcc)));
+        }
+        Ok(())
+    }
+
+    /// The extended type of the next box, if it is a `uuid` box.
+    pub fn peek_usertype(&self) -> io::Result<Option<Uuid>> {
+        let (header, mut body) = self.clone().read_box()?;
+        if header.fourcc != FourCC::new(b"uuid") || body.left() < 16 {
+            return Ok(None);
+        }
+        Uuid::read_from(&mut body).map(Some)
+    }
+
+    /// Read and check the extended type of a `uuid` box.
+    pub fn expect_usertype(&mut self, header: &BoxHeader, usertype: Uuid) -> io::Result<()> {
+        let u = Uuid::read_from(self)?;
+        if u != usertype {
+            return Err(invalid_data(format!("{}: expected extended type {}, got {}", header.fourcc, usertype, u)));
+        }
+        Ok(())
+    }
+
+    /// Check the version of a FullBox.
+    pub fn expect_version(&self, header: &BoxHeader, version: u8, expected: u8) -> io::Result<()> {
+        if version != expected {
+            return Err(invalid_data(format!("{}: unsupported version {}", header.fourcc, version)));
+        }
+        Ok(())
+    }
+
+    /// Read a class that has arguments, like `SampleDescriptionBox(handler_type)`.
+    pub fn read_with_args<T: ReadFrom>(&mut self, args: Vec<i64>) -> io::Result<T> {
+        self.args = args;
+        let res = T::read_from(self);
+        self.args.clear();
+        res
+    }
+
+    /// Take the arguments that were passed to `read_with_args`.
+    pub fn take_args(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.args)
+    }
+
+    /// Value of argument `idx` of a class. If it was not passed, it is
+    /// looked up by `name` in the values known from enclosing boxes.
+    pub fn class_arg(&self, args: &[i64], idx: usize, name: &str) -> i64 {
+        args.get(idx).cloned().or_else(|| self.var(name)).unwrap_or(0)
+    }
+
+    /// Make a value known to the rest of this box, and the boxes in it.
+    pub fn set_var(&mut self, name: &str, value: i64) {
+        self.ctx.set(name, value);
+    }
+
+    /// A value known from an enclosing box.
+    pub fn var(&self, name: &str) -> Option<i64> {
+        self.ctx.get(name)
+    }
+
+    /// Value of a variable that is not part of the class that
+    /// is being read, like a field of an enclosing class.
+    pub fn lookup(&self, name: &str) -> io::Result<i64> {
+        self.ctx.lookup(name)
+    }
+
+    /// Check that all data has been read.
+    pub fn finish(&self, header: &BoxHeader) -> io::Result<()> {
+        if self.left_bits() > 0 {
+            return Err(invalid_data(format!("{}: {} bytes of trailing data", header.fourcc, self.left())));
+        }
+        Ok(())
+    }
+}
+
+impl ReadFrom for RawBox {
+    fn read_from(r: &mut BoxReader) -> io::Result<RawBox> {
+        let (header, mut body) = r.read_box()?;
+        let data = body.read(body.left())?;
+        Ok(RawBox {
+            fourcc: header.fourcc,
+            data:   data.to_vec(),
+            size_form: header.size_form,
+        })
+    }
+}
+
+impl ReadFrom for Uuid {
+    fn read_from(r: &mut BoxReader) -> io::Result<Uuid> {
+        let mut u = Uuid::default();
+        u.0.copy_from_slice(r.read(16)?);
+        Ok(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_bits() {
+        // bit(1) + int(31), then int(5)[3] and a bit(1): 10001 00011 11111 1.
+        let mut r = BoxReader::new(&[ 0x80, 0, 0, 0x2a, 0x88, 0xff ]);
+        assert_eq!(r.read_uint(1).unwrap(), 1);
+        assert_eq!(r.read_uint(31).unwrap(), 42);
+        assert_eq!(r.read_uint(5).unwrap(), 17);
+        assert_eq!(r.read_uint(5).unwrap(), 3);
+        assert_eq!(r.read_uint(5).unwrap(), 31);
+        assert_eq!(r.read_uint(1).unwrap(), 1);
+        assert_eq!(r.left(), 0);
+    }
+
+    #[test]
+    fn read_signed() {
+        // int(12) -1 and -2048, then int(8) 127.
+        let mut r = BoxReader::new(&[ 0xff, 0xf8, 0x00, 0x7f ]);
+        assert_eq!(r.read_int(12).unwrap(), -1);
+        assert_eq!(r.read_int(12).unwrap(), -2048);
+        assert_eq!(r.read_int(8).unwrap(), 127);
+        let mut r = BoxReader::new(&[ 0x80; 8 ]);
+        assert_eq!(r.read_int(64).unwrap(), i64::from_be_bytes([ 0x80; 8 ]));
+    }
+
+    #[test]
+    fn read_past_end() {
+        let mut r = BoxReader::new(&[ 0xff, 0xff ]);
+        assert_eq!(r.read_uint(65).unwrap_err().kind(), io::ErrorKind::Other);
+        assert_eq!(r.read_uint(3).unwrap(), 7);
+        assert_eq!(r.read_uint(14).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(r.read_uint(13).unwrap(), 0x1fff);
+        assert_eq!(r.read_uint(1).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,154 @@
+// Boxes that are defined outside of this crate.
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::sync::RwLock;
+
+use crate::node::{BoxInfo, BoxNode};
+use crate::reader::{BoxReader, ReadFrom};
+use crate::writer::{BoxWriter, WriteTo};
+use crate::types::{FourCC, Uuid};
+
+/// A box type that can be registered with `register_box`.
+///
+/// This is implemented automatically for every type that has the
+/// traits that `def_box!` implements for a box with a fixed fourcc.
+pub trait AnyBox: Any + fmt::Debug + Send + Sync {
+    /// The fourcc of this box.
+    fn fourcc(&self) -> FourCC;
+    /// The child boxes, if this is a container box.
+    fn children(&self) -> Option<&[BoxNode]>;
+    /// Write the box.
+    fn write_box(&self, w: &mut BoxWriter) -> io::Result<()>;
+    fn clone_box(&self) -> Box<dyn AnyBox>;
+    fn eq_box(&self, other: &dyn AnyBox) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T> AnyBox for T
+where
+    T: BoxInfo + WriteTo + Clone + PartialEq + fmt::Debug + Send + Sync + 'static,
+{
+    fn fourcc(&self) -> FourCC {
+        T::FOURCC
+    }
+
+    fn children(&self) -> Option<&[BoxNode]> {
+        BoxInfo::children(self)
+    }
+
+    fn write_box(&self, w: &mut BoxWriter) -> io::Result<()> {
+        self.write_to(w)
+    }
+
+    fn clone_box(&self) -> Box<dyn AnyBox> {
+        Box::new(self.clone())
+    }
+
+    fn eq_box(&self, other: &dyn AnyBox) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A box of a type that was registered with `register_box`.
+pub struct UserBox(Box<dyn AnyBox>);
+
+impl UserBox {
+    /// Wrap a box.
+    pub fn new(b: impl AnyBox) -> UserBox {
+        UserBox(Box::new(b))
+    }
+
+    /// The box, if it is a `T`.
+    pub fn downcast_ref<T: AnyBox>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref::<T>()
+    }
+
+    /// The fourcc of this box.
+    pub fn fourcc(&self) -> FourCC {
+        self.0.fourcc()
+    }
+
+    /// The child boxes, if this is a container box.
+    pub fn children(&self) -> Option<&[BoxNode]> {
+        self.0.children()
+    }
+}
+
+impl Clone for UserBox {
+    fn clone(&self) -> UserBox {
+        UserBox(self.0.clone_box())
+    }
+}
+
+impl PartialEq for UserBox {
+    fn eq(&self, other: &UserBox) -> bool {
+        self.0.eq_box(&*other.0)
+    }
+}
+
+impl fmt::Debug for UserBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl WriteTo for UserBox {
+    fn write_to(&self, w: &mut BoxWriter) -> io::Result<()> {
+        self.0.write_box(w)
+    }
+}
+
+struct Entry {
+    fourcc:     FourCC,
+    usertype:   Option<Uuid>,
+    read:       fn(&mut BoxReader) -> io::Result<UserBox>,
+}
+
+static REGISTRY: RwLock<Vec<Entry>> = RwLock::new(Vec::new());
+
+fn read_user<T: AnyBox + ReadFrom>(r: &mut BoxReader) -> io::Result<UserBox> {
+    Ok(UserBox::new(T::read_from(r)?))
+}
+
+/// Register a box type that is defined outside of this crate, usually
+/// with `def_box!`. After this, `BoxNode` decodes boxes with its
+/// fourcc (and extended type, for a `uuid` box) as a `BoxNode::User`.
+///
+/// Registered boxes are tried before the boxes of this crate, so a
+/// box type can also be replaced by another definition.
+pub fn register_box<T: AnyBox + BoxInfo + ReadFrom>() {
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    registry.retain(|e| e.fourcc != T::FOURCC || e.usertype != T::USERTYPE);
+    registry.push(Entry {
+        fourcc:     T::FOURCC,
+        usertype:   T::USERTYPE,
+        read:       read_user::<T>,
+    });
+}
+
+// Try to read a registered box. Returns `None` if there is no
+// registered box for this fourcc, or if it could not be decoded.
+pub(crate) fn read_registered(r: &mut BoxReader, fourcc: FourCC, usertype: Option<Uuid>) -> Option<UserBox> {
+    // Not with the lock held, a container box reads its children
+    // through here as well.
+    let readers: Vec<_> = {
+        let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+        registry.iter().rev()
+            .filter(|e| e.fourcc == fourcc && e.usertype.is_none_or(|u| Some(u) == usertype))
+            .map(|e| e.read)
+            .collect()
+    };
+    for read in readers {
+        let mut r2 = r.clone();
+        if let Ok(b) = read(&mut r2) {
+            *r = r2;
+            return Some(b);
+        }
+    }
+    None
+}
@@ -1,5 +1,12 @@
 use iso_bmff_macros::def_box;
 
+use crate::drc::{DRCCoefficientsBasic, DRCCoefficientsUniDRC, DRCInstructionsBasic, DRCInstructionsUniDRC};
+use crate::fixed::{Fixed8_8, Fixed16_16, Matrix, UFixed16_16};
+use crate::flags::{SampleFlags, TrackFragmentHeaderFlags, TrackHeaderFlags, TrackRunFlags};
+use crate::language::Language;
+use crate::node::{BoxNode, SampleGroupEntry};
+use crate::types::Uuid;
+
 // File Type Box
 def_box! {
     aligned(8) class FileTypeBox
@@ -19,7 +26,15 @@ def_box! {
 
 // Free Space Box
 def_box! {
-    aligned(8) class FreeSpaceBox extends Box(free_type) {
+    aligned(8) class FreeSpaceBox extends Box(free_type) # fourcc: "free", "skip" {
+       unsigned int(8) data[];
+    }
+}
+
+// A box with an extended type (`usertype`) that is not otherwise known.
+def_box! {
+    aligned(8) class UuidBox extends Box("uuid") {
+       Uuid usertype;
        unsigned int(8) data[];
     }
 }
@@ -55,12 +70,11 @@ def_box! {
           unsigned int(32) timescale;
           unsigned int(32) duration;
        }
-       template int(32) rate = 0x00010000; // typically 1.0
-       template int(16) volume = 0x0100;    // typically, full volume
+       template int(32) rate = 0x00010000; # rust_type: Fixed16_16 // typically 1.0
+       template int(16) volume = 0x0100; # rust_type: Fixed8_8  // typically, full volume
        const bit(16) reserved = 0;
        const unsigned int(32)[2] reserved = 0;
-       template int(32)[9] matrix =
-          { 0x00010000,0,0,0,0x00010000,0,0,0,0x40000000 };
+       template Matrix matrix;
           // Unity matrix
        bit(32)[6] pre_defined = 0;
        unsigned int(32) next_track_ID;
@@ -76,7 +90,7 @@ def_box! {
 // Track Header Box
 def_box! {
     aligned(8) class TrackHeaderBox
-       extends FullBox("tkhd", version, flags){
+       extends FullBox("tkhd", version, flags) # rust_type: TrackHeaderFlags {
        if (version==1) {
           unsigned int(64) creation_time;
           unsigned int(64) modification_time;
@@ -93,13 +107,12 @@ def_box! {
        const unsigned int(32)[2] reserved = 0;
        template int(16) layer = 0;
        template int(16) alternate_group = 0;
-       template int(16) volume = {if track_is_audio 0x0100 else 0};
+       template int(16) volume = {if track_is_audio 0x0100 else 0}; # rust_type: Fixed8_8
        const unsigned int(16) reserved = 0;
-       template int(32)[9] matrix=
-          { 0x00010000,0,0,0,0x00010000,0,0,0,0x40000000 };
+       template Matrix matrix;
           // unity matrix
-       unsigned int(32) width;
-       unsigned int(32) height;
+       unsigned int(32) width; # rust_type: UFixed16_16
+       unsigned int(32) height; # rust_type: UFixed16_16
     }
 }
 
@@ -110,7 +123,7 @@ def_box! {
 
 def_box! {
     aligned(8) class TrackReferenceTypeBox (unsigned int(32) reference_type) extends
-    Box(reference_type) {
+    Box(reference_type) # parent: "tref" {
        unsigned int(32) track_IDs[];
     }
 }
@@ -122,7 +135,7 @@ def_box! {
 
 def_box! {
     aligned(8) class TrackGroupTypeBox(unsigned int(32) track_group_type) extends
-    FullBox(track_group_type, version = 0, flags = 0)
+    FullBox(track_group_type, version = 0, flags = 0) # parent: "trgr"
     {
        unsigned int(32) track_group_id;
        // the remaining data may be specified for a particular track_group_type
@@ -150,7 +163,7 @@ def_box! {
           unsigned int(32) duration;
        }
        bit(1)   pad = 0;
-       unsigned int(5)[3]   language;   // ISO-639-2/T language code
+       Language   language;   // ISO-639-2/T language code
        unsigned int(16) pre_defined = 0;
     }
 }
@@ -193,7 +206,7 @@ def_box! {
 
 def_box! {
     aligned(8) abstract class SampleEntry (unsigned int(32) format)
-       extends Box(format){
+       extends Box(format) # parent: "stsd" {
        const unsigned int(8)[6] reserved = 0;
        unsigned int(16) data_reference_index;
     }
@@ -213,7 +226,7 @@ def_box! {
        int i ;
        unsigned int(32) entry_count;
        for (i = 1 ; i <= entry_count ; i++){
-          SampleEntry();    // an instance of a class derived from SampleEntry
+          SampleEntry(); # rust_type: BoxNode // an instance of a class derived from SampleEntry
        }
     }
 }
@@ -370,7 +383,7 @@ def_box! {
        extends FullBox("dref", version = 0, 0) {
        unsigned int(32) entry_count;
        for (i=1; i <= entry_count; i++) {
-          DataEntryBox(entry_version, entry_flags) data_entry;
+          Box data_entry;   // DataEntryUrlBox or DataEntryUrnBox
        }
     }
 }
@@ -510,7 +523,7 @@ def_box! {
        unsigned int(32) default_sample_description_index;
        unsigned int(32) default_sample_duration;
        unsigned int(32) default_sample_size;
-       unsigned int(32) default_sample_flags;
+       SampleFlags default_sample_flags;
     }
 }
 
@@ -537,34 +550,36 @@ def_box! {
 // Track Fragment Header Box
 def_box! {
     aligned(8) class TrackFragmentHeaderBox
-             extends FullBox("tfhd", 0, tf_flags){
+             extends FullBox("tfhd", 0, tf_flags) # rust_type: TrackFragmentHeaderFlags {
        unsigned int(32) track_ID;
        // all the following are optional fields
-       unsigned int(64) base_data_offset;
-       unsigned int(32) sample_description_index;
-       unsigned int(32) default_sample_duration;
-       unsigned int(32) default_sample_size;
-       unsigned int(32) default_sample_flags;
+       if (tf_flags & 0x000001) { unsigned int(64) base_data_offset; }
+       if (tf_flags & 0x000002) { unsigned int(32) sample_description_index; }
+       if (tf_flags & 0x000008) { unsigned int(32) default_sample_duration; }
+       if (tf_flags & 0x000010) { unsigned int(32) default_sample_size; }
+       if (tf_flags & 0x000020) { SampleFlags default_sample_flags; }
     }
 }
 
 // Track Fragment Run Box
 def_box! {
     aligned(8) class TrackRunBox
-             extends FullBox("trun", version, tr_flags) {
+             extends FullBox("trun", version, tr_flags) # rust_type: TrackRunFlags {
        unsigned int(32) sample_count;
        // the following are optional fields
-       signed int(32) data_offset;
-       unsigned int(32) first_sample_flags;
+       if (tr_flags & 0x000001) { signed int(32) data_offset; }
+       if (tr_flags & 0x000004) { SampleFlags first_sample_flags; }
        // all fields in the following array are optional
        {
-          unsigned int(32) sample_duration;
-          unsigned int(32) sample_size;
-          unsigned int(32) sample_flags
-          if (version == 0)
-             { unsigned int(32)   sample_composition_time_offset; }
-          else
-             { signed int(32)     sample_composition_time_offset; }
+          if (tr_flags & 0x000100) { unsigned int(32) sample_duration; }
+          if (tr_flags & 0x000200) { unsigned int(32) sample_size; }
+          if (tr_flags & 0x000400) { SampleFlags sample_flags; }
+          if (tr_flags & 0x000800) {
+             if (version == 0)
+                { unsigned int(32)   sample_composition_time_offset; }
+             else
+                { signed int(32)     sample_composition_time_offset; }
+          }
        }[ sample_count ]
     }
 }
@@ -691,42 +706,42 @@ def_box! {
 }
 
 def_box! {
-    abstract class SampleGroupDescriptionEntry (unsigned int(32) grouping_type)
+    abstract class SampleGroupDescriptionEntry (unsigned int(32) grouping_type) # parent: "sgpd"
     {
     }
 }
 
 def_box! {
     abstract class VisualSampleGroupEntry (unsigned int(32) grouping_type) extends
-    SampleGroupDescriptionEntry (grouping_type)
+    SampleGroupDescriptionEntry (grouping_type) # handler: "vide"
     {
     }
 }
 
 def_box! {
     abstract class AudioSampleGroupEntry (unsigned int(32) grouping_type) extends
-    SampleGroupDescriptionEntry (grouping_type)
+    SampleGroupDescriptionEntry (grouping_type) # handler: "soun"
     {
     }
 }
 
 def_box! {
     abstract class HintSampleGroupEntry (unsigned int(32) grouping_type) extends
-    SampleGroupDescriptionEntry (grouping_type)
+    SampleGroupDescriptionEntry (grouping_type) # handler: "hint"
     {
     }
 }
 
 def_box! {
     abstract class SubtitleSampleGroupEntry (unsigned int(32) grouping_type) extends
-    SampleGroupDescriptionEntry (grouping_type)
+    SampleGroupDescriptionEntry (grouping_type) # handler: "subt"
     {
     }
 }
 
 def_box! {
     abstract class TextSampleGroupEntry (unsigned int(32) grouping_type) extends
-    SampleGroupDescriptionEntry (grouping_type)
+    SampleGroupDescriptionEntry (grouping_type) # handler: "text"
     {
     }
 }
@@ -734,8 +749,9 @@ def_box! {
 def_box! {
     aligned(8) class SampleGroupDescriptionBox (unsigned int(32) handler_type)
        extends FullBox("sgpd", version, 0){
-       unsigned int(32) grouping_type;
-       if (version==1) { unsigned int(32) default_length; }
+       unsigned int(32) grouping_type; # export: grouping_type
+       if (version==1) { unsigned int(32) default_length; # export: default_length
+       }
          if (version>=2) {
             unsigned int(32) default_sample_description_index;
          }
@@ -744,10 +760,10 @@ def_box! {
          for (i = 1 ; i <= entry_count ; i++){
             if (version==1) {
                if (default_length==0) {
-                  unsigned int(32) description_length;
+                  unsigned int(32) description_length; # export: description_length
                }
             }
-            SampleGroupEntry (grouping_type);
+            SampleGroupDescriptionEntry (grouping_type); # rust_type: SampleGroupEntry
                // an instance of a class derived from SampleGroupEntry
                // that is appropriate and permitted for the media type
          }
@@ -765,7 +781,7 @@ def_box! {
     aligned(8) class CopyrightBox
        extends FullBox("cprt", version = 0, 0) {
        const bit(1)   pad = 0;
-       unsigned int(5)[3]   language;   // ISO-639-2/T language code
+       Language   language;   // ISO-639-2/T language code
        string   notice;
     }
 }
@@ -792,18 +808,49 @@ def_box! {
     aligned(8) class MetaBox (unsigned int(32) handler_type)
        extends FullBox("meta", version = 0, 0) {
        HandlerBox(handler_type)   theHandler;
-       PrimaryItemBox       primary_resource;                    //   optional
-       DataInformationBox   file_locations;                      //   optional
-       ItemLocationBox      item_locations;                      //   optional
-       ItemProtectionBox    protections;                         //   optional
-       ItemInfoBox          item_infos;                          //   optional
-       IPMPControlBox       IPMP_control;                        //   optional
-       ItemReferenceBox     item_refs;                           //   optional
-       ItemDataBox          item_data;                           //   optional
+       PrimaryItemBox       primary_resource;                    # optional
+       DataInformationBox   file_locations;                      # optional
+       ItemLocationBox      item_locations;                      # optional
+       ItemProtectionBox    protections;                         # optional
+       ItemInfoBox          item_infos;                          # optional
+       Box                  IPMP_control;                        # optional // IPMPControlBox
+       ItemReferenceBox     item_refs;                           # optional
+       ItemDataBox          item_data;                           # optional
        Box   other_boxes[];                                      //   optional
     }
 }
 
+// Item Reference Box
+def_box! {
+    aligned(8) class SingleItemTypeReferenceBox(unsigned int(32) referenceType) extends Box(referenceType) {
+       unsigned int(16) from_item_ID;
+       unsigned int(16) reference_count;
+       for (j=0; j<reference_count; j++) {
+          unsigned int(16) to_item_ID;
+       }
+    }
+}
+
+def_box! {
+    aligned(8) class SingleItemTypeReferenceBoxLarge(unsigned int(32) referenceType) extends Box(referenceType) {
+       unsigned int(32) from_item_ID;
+       unsigned int(16) reference_count;
+       for (j=0; j<reference_count; j++) {
+          unsigned int(32) to_item_ID;
+       }
+    }
+}
+
+def_box! {
+    aligned(8) class ItemReferenceBox extends FullBox("iref", version, 0) {
+       if (version==0) {
+          SingleItemTypeReferenceBox       references[];
+       } else if (version==1) {
+          SingleItemTypeReferenceBoxLarge  references_large[];
+       }
+    }
+}
+
 def_box! {
     aligned(8) class XMLBox
           extends FullBox("xml ", version = 0, 0) {
@@ -972,8 +1019,8 @@ def_box! {
 def_box! {
     aligned(8) class ProtectionSchemeInfoBox(unsigned int(32) fmt) extends Box("sinf") {
        OriginalFormatBox(fmt) original_format;
-         SchemeTypeBox                 scheme_type_box;    // optional
-         SchemeInformationBox          info;        // optional
+         SchemeTypeBox                 scheme_type_box;    # optional
+         SchemeInformationBox          info;        # optional
     }
 }
 
@@ -1008,8 +1055,8 @@ def_box! {
 def_box! {
     aligned(8) class PartitionEntry extends Box("paen") {
        FilePartitionBox blocks_and_symbols;
-       FECReservoirBox   FEC_symbol_locations; //optional
-       FileReservoirBox File_symbol_locations; //optional
+       FECReservoirBox   FEC_symbol_locations; # optional
+       FileReservoirBox File_symbol_locations; # optional
     }
 }
 
@@ -1018,8 +1065,8 @@ def_box! {
           extends FullBox("fiin", version = 0, 0) {
        unsigned int(16) entry_count;
        PartitionEntry    partition_entries[ entry_count ];
-       FDSessionGroupBox session_info;     //optional
-       GroupIdToNameBox group_id_to_name; //optional
+       FDSessionGroupBox session_info;     # optional
+       GroupIdToNameBox group_id_to_name; # optional
     }
 }
 
@@ -1160,7 +1207,7 @@ def_box! {
     aligned(8) class RestrictedSchemeInfoBox(unsigned int(32) fmt) extends Box("rinf") {
        OriginalFormatBox(fmt) original_format;
        SchemeTypeBox           scheme_type_box;
-       SchemeInformationBox    info;        // optional
+       SchemeInformationBox    info;        # optional
     }
 }
 
@@ -1271,7 +1318,8 @@ def_box! {
 }
 
 def_box! {
-    class MPEG2TSSampleEntry(unsigned int(32) name) extends HintSampleEntry(name) {
+    // Only the base of the entries below.
+    abstract class MPEG2TSSampleEntry(unsigned int(32) name) extends HintSampleEntry(name) {
        uint(16) hinttrackversion = 1;
        uint(16) highestcompatibleversion = 1;
        uint(8) precedingbyteslen;
@@ -1284,7 +1332,7 @@ def_box! {
 
 def_box! {
     class ProtectedMPEG2TransportStreamSampleEntry
-       extends MPEG2TransportStreamSampleEntry("pm2t") {
+       extends MPEG2TSSampleEntry("pm2t") {
        ProtectionSchemeInfoBox    SchemeInformation;
     }
 }
@@ -1303,6 +1351,14 @@ def_box! {
     }
 }
 
+def_box! {
+    class RtpReceptionHintSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # fourcc: "rrtp"; handler: "hint" {
+       uint(16) hinttrackversion = 1;
+       uint(16) highestcompatibleversion = 1;
+       uint(32) maxpacketsize;
+    }
+}
+
 def_box! {
     class ProtectedRtpReceptionHintSampleEntry
        extends RtpReceptionHintSampleEntry ("prtp") {
@@ -1350,6 +1406,23 @@ def_box! {
     }
 }
 
+// Alternative Startup Sequences
+def_box! {
+    class AlternativeStartupEntry() extends VisualSampleGroupEntry ("alst")
+    {
+      unsigned int(16) roll_count;
+      unsigned int(16) first_output_sample;
+      for (i=1; i <= roll_count; i++)
+        unsigned int(32) sample_offset[i];
+      j=1;
+      do { // optional, until the end of the structure
+        unsigned int(16) num_output_samples[j];
+        unsigned int(16) num_total_samples[j];
+        j++;
+      }
+    }
+}
+
 // Random Access Point (RAP) Sample Grouping
 def_box! {
     class VisualRandomAccessEntry() extends VisualSampleGroupEntry ("rap ")
@@ -1389,22 +1462,25 @@ def_box! {
 
 // Sample entry
 def_box! {
-    class VisualSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname){
+    class VisualSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "vide" {
        unsigned int(16) pre_defined = 0;
        const unsigned int(16) reserved = 0;
        unsigned int(32)[3] pre_defined = 0;
        unsigned int(16) width;
        unsigned int(16) height;
-       template unsigned int(32) horizresolution = 0x00480000; // 72 dpi
-       template unsigned int(32) vertresolution = 0x00480000; // 72 dpi
+       template unsigned int(32) horizresolution = 0x00480000; # rust_type: UFixed16_16 // 72 dpi
+       template unsigned int(32) vertresolution = 0x00480000; # rust_type: UFixed16_16 // 72 dpi
        const unsigned int(32) reserved = 0;
        template unsigned int(16) frame_count = 1;
        string[32] compressorname;
        template unsigned int(16) depth = 0x0018;
        int(16) pre_defined = -1;
-       // other boxes from derived specifications
-       CleanApertureBox     clap;    // optional
-       PixelAspectRatioBox pasp;     // optional
+       // other boxes from derived specifications, like the decoder
+       // configuration (avcC), and the colour information of 12.1.5.
+       Box ();
+       ColourInformationBox colr[];
+       CleanApertureBox     clap;    # optional
+       PixelAspectRatioBox pasp;     # optional
     }
 }
 
@@ -1442,11 +1518,11 @@ def_box! {
        }
        else if (colour_type == "rICC")
        {
-          ICC_profile;   // restricted ICC profile
+          unsigned int(8) ICC_profile[];   // restricted ICC profile
        }
        else if (colour_type == "prof")
        {
-          ICC_profile;   // unrestricted ICC profile
+          unsigned int(8) ICC_profile[];   // unrestricted ICC profile
        }
     }
 }
@@ -1455,20 +1531,20 @@ def_box! {
 def_box! {
     aligned(8) class SoundMediaHeaderBox
        extends FullBox("smhd", version = 0, 0) {
-       template int(16) balance = 0;
+       template int(16) balance = 0; # rust_type: Fixed8_8
        const unsigned int(16) reserved = 0;
     }
 }
 
 def_box! {
-    class AudioSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname){
+    class AudioSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "soun" {
        const unsigned int(32)[2] reserved = 0;
-       template unsigned int(16) channelcount = 2;
+       template unsigned int(16) channelcount = 2; # export: channelCount, baseChannelCount
        template unsigned int(16) samplesize = 16;
        unsigned int(16) pre_defined = 0;
        const unsigned int(16) reserved = 0 ;
        template unsigned int(32) samplerate = { default samplerate of media}<<16;
-       ChannelLayout();
+       ChannelLayout(); # optional
        // we permit any number of DownMix or DRC boxes:
        DownMixInstructions() [];
        DRCCoefficientsBasic() [];
@@ -1486,18 +1562,18 @@ def_box! {
 }
 
 def_box! {
-    class AudioSampleEntryV1(unsigned int(32) codingname) extends SampleEntry (codingname){
+    class AudioSampleEntryV1(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "soun" {
        unsigned int(16) entry_version; // must be 1,
                                   // and must be in an stsd with version ==1
        const unsigned int(16)[3] reserved = 0;
-       template unsigned int(16) channelcount; // must be correct
+       template unsigned int(16) channelcount; # export: channelCount, baseChannelCount // must be correct
        template unsigned int(16) samplesize = 16;
        unsigned int(16) pre_defined = 0;
        const unsigned int(16) reserved = 0 ;
        template unsigned int(32) samplerate = 1<<16;
        // optional boxes follow
-       SamplingRateBox();
-       ChannelLayout();
+       SamplingRateBox(); # optional
+       ChannelLayout(); # optional
        // we permit any number of DownMix or DRC boxes:
        DownMixInstructions() [];
        DRCCoefficientsBasic() [];
@@ -1575,7 +1651,22 @@ def_box! {
 }
 
 def_box! {
-    class MetaDataSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) {
+    aligned(8) class TrackLoudnessInfo extends LoudnessBaseBox("tlou") { }
+}
+
+def_box! {
+    aligned(8) class AlbumLoudnessInfo extends LoudnessBaseBox ("alou") { }
+}
+
+def_box! {
+    aligned(8) class LoudnessBox extends Box("ludt") {
+       TrackLoudnessInfo loudness[]; // a set of one or more loudness boxes
+       AlbumLoudnessInfo albumLoudness[]; // if applicable
+    }
+}
+
+def_box! {
+    class MetaDataSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "meta" {
        Box[] other_boxes; // optional
     }
 }
@@ -1584,13 +1675,13 @@ def_box! {
     class XMLMetaDataSampleEntry() extends MetaDataSampleEntry ("metx") {
        string   content_encoding; // optional
        string   namespace;
-       string   schema_location; // optional
-       BitRateBox (); // optional
+       string   schema_location; # optional
+       BitRateBox (); # optional
     }
 }
 
 def_box! {
-    class TextConfigBox() extends Fullbox ("txtC", 0, 0) {
+    class TextConfigBox() extends FullBox ("txtC", 0, 0) {
        string   text_config;
     }
 }
@@ -1599,8 +1690,8 @@ def_box! {
     class TextMetaDataSampleEntry() extends MetaDataSampleEntry ("mett") {
        string   content_encoding; // optional
        string   mime_format;
-       BitRateBox ();    // optional
-       TextConfigBox (); // optional
+       BitRateBox ();    # optional
+       TextConfigBox (); # optional
     }
 }
 
@@ -1620,21 +1711,21 @@ def_box! {
 
 def_box! {
     class URIMetaSampleEntry() extends MetaDataSampleEntry ("urim") {
-       URIbox         the_label;
-       URIInitBox     init;    // optional
-       BitRateBox ();          // optional
+       URIBox         the_label;
+       URIInitBox     init;    # optional
+       BitRateBox ();          # optional
     }
 }
 
 // Sample entry
 def_box! {
-    class HintSampleEntry() extends SampleEntry (protocol) {
+    class HintSampleEntry() extends SampleEntry (protocol) # handler: "hint" {
        unsigned int(8) data [];
     }
 }
 
 def_box! {
-    class PlainTextSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) {
+    class PlainTextSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "text" {
     }
 }
 
@@ -1642,8 +1733,8 @@ def_box! {
     class SimpleTextSampleEntry(unsigned int(32) codingname) extends PlainTextSampleEntry ("stxt") {
        string   content_encoding; // optional
        string   mime_format;
-       BitRateBox ();             // optional
-       TextConfigBox ();          // optional
+       BitRateBox ();             # optional
+       TextConfigBox ();          # optional
     }
 }
 
@@ -1655,17 +1746,17 @@ def_box! {
 }
 
 def_box! {
-    class SubtitleSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) {
+    class SubtitleSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "subt" {
     }
 }
 
 def_box! {
     class XMLSubtitleSampleEntry() extends SubtitleSampleEntry ("stpp") {
        string   namespace;
-       string   schema_location; // optional
-       string   auxiliary_mime_types;
-                      // optional, required if auxiliary resources are present
-       BitRateBox ();             // optional
+       string   schema_location; # optional
+       // optional, required if auxiliary resources are present
+       string   auxiliary_mime_types; # optional
+       BitRateBox ();             # optional
     }
 }
 
@@ -1673,16 +1764,162 @@ def_box! {
     class TextSubtitleSampleEntry() extends SubtitleSampleEntry ("sbtt") {
        string   content_encoding; // optional
        string   mime_format;
-       BitRateBox ();             // optional
-       TextConfigBox ();          // optional
+       BitRateBox ();             # optional
+       TextConfigBox ();          # optional
     }
 }
 
 // Sample entry
 def_box! {
-    class FontSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname){
+    class FontSampleEntry(unsigned int(32) codingname) extends SampleEntry (codingname) # handler: "fdsm" {
        //other boxes from derived specifications
-       BitRateBox (); // optional
+       BitRateBox (); # optional
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::reader::{BoxReader, ReadFrom};
+    use crate::writer::{BoxWriter, WriteTo};
+
+    fn write<T: WriteTo>(b: &T) -> Vec<u8> {
+        let mut w = BoxWriter::new();
+        b.write_to(&mut w).unwrap();
+        w.into_inner()
+    }
+
+    fn read<T: ReadFrom>(data: &[u8]) -> T {
+        T::read_from(&mut BoxReader::new(data)).unwrap()
+    }
+
+    #[test]
+    fn brace_defaults() {
+        let unity = Matrix::for_rotation(0).unwrap();
+        let mvhd = MovieHeaderBox::default();
+        assert_eq!(mvhd.matrix, unity);
+        assert_eq!((mvhd.rate.to_f64(), mvhd.volume.to_f64()), (1.0, 1.0));
+
+        let tkhd = TrackHeaderBox::default();
+        assert_eq!(tkhd.matrix, unity);
+        assert_eq!(tkhd.volume, Fixed8_8(0));
+        let audio = TrackHeaderBox::default_in(&Context::new().with("track_is_audio", 1));
+        assert_eq!(audio.volume, Fixed8_8(0x0100));
+
+        // The unity matrix is written as in the spec.
+        let data = write(&tkhd);
+        assert_eq!(&data[48 .. 84], &[
+            0, 1, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,
+            0, 0, 0, 0,  0, 1, 0, 0,  0, 0, 0, 0,
+            0, 0, 0, 0,  0, 0, 0, 0,  0x40, 0, 0, 0,
+        ]);
+        assert_eq!(read::<TrackHeaderBox>(&data), tkhd);
+    }
+
+    #[test]
+    fn with_raises_version() {
+        let mvhd = MovieHeaderBox::default().with_duration(1000);
+        assert_eq!(mvhd.version, 0);
+        let mvhd = mvhd.with_duration(1 << 40);
+        assert_eq!(mvhd.version, 1);
+        assert_eq!(mvhd.min_version(), 1);
+        assert_eq!(read::<MovieHeaderBox>(&write(&mvhd)), mvhd);
+
+        // A field that is only there in version 1.
+        let assp = AlternativeStartupSequencePropertiesBox::default();
+        assert_eq!(assp.min_version(), 0);
+        let assp = assp.with_entries(vec![Default::default()]);
+        assert_eq!(assp.version, 1);
+    }
+
+    #[test]
+    fn version_on_write() {
+        // A duration that does not fit in 32 bits is written as version 1.
+        let mdhd = MediaHeaderBox { duration: 1 << 33, ..Default::default() };
+        let data = write(&mdhd);
+        assert_eq!(data[8], 1);
+        let back = read::<MediaHeaderBox>(&data);
+        assert_eq!((back.version, back.duration), (1, 1 << 33));
+
+        let elst = EditListBox {
+            entries: vec![EditListBoxEntry { media_time: -1, ..Default::default() }],
+            ..Default::default()
+        };
+        assert_eq!(write(&elst)[8], 0);
+        let elst = EditListBox {
+            entries: vec![EditListBoxEntry { media_time: -1 << 40, ..Default::default() }],
+            ..Default::default()
+        };
+        assert_eq!(write(&elst)[8], 1);
+
+        // A version 1 box with small values stays version 1.
+        let mvhd = MovieHeaderBox { version: 1, duration: 1000, ..Default::default() };
+        let data = write(&mvhd);
+        assert_eq!((data[8], data.len()), (1, 120));
+        let back = read::<MovieHeaderBox>(&data);
+        assert_eq!(back.version, 1);
+        assert_eq!(write(&back), data);
+    }
+
+    #[test]
+    fn presence_flags() {
+        let trun = TrackRunBox {
+            data_offset: Some(8),
+            samples: vec![
+                TrackRunBoxSample { sample_size: Some(100), ..Default::default() },
+                TrackRunBoxSample { sample_size: Some(200), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        let data = write(&trun);
+        assert_eq!(&data[9 .. 12], &[ 0x00, 0x02, 0x01 ]);
+        let back = read::<TrackRunBox>(&data);
+        assert_eq!(back.tr_flags, TrackRunFlags::DATA_OFFSET_PRESENT | TrackRunFlags::SAMPLE_SIZE_PRESENT);
+        assert_eq!(back.samples[1].sample_size, Some(200));
+        assert_eq!(back.samples[1].sample_duration, None);
+
+        // A flag without its field is cleared.
+        let trun = TrackRunBox { tr_flags: TrackRunFlags::SAMPLE_DURATION_PRESENT, ..back };
+        assert_eq!(&write(&trun)[9 .. 12], &[ 0x00, 0x02, 0x01 ]);
+
+        // Flags that are not about presence are kept.
+        let tfhd = TrackFragmentHeaderBox {
+            tf_flags: TrackFragmentHeaderFlags::DEFAULT_BASE_IS_MOOF,
+            default_sample_flags: Some(SampleFlags::sync()),
+            ..Default::default()
+        };
+        let data = write(&tfhd);
+        assert_eq!(&data[9 .. 12], &[ 0x02, 0x00, 0x20 ]);
+        assert_eq!(read::<TrackFragmentHeaderBox>(&data), TrackFragmentHeaderBox {
+            tf_flags: TrackFragmentHeaderFlags::DEFAULT_BASE_IS_MOOF | TrackFragmentHeaderFlags::DEFAULT_SAMPLE_FLAGS_PRESENT,
+            ..tfhd
+        });
+    }
+
+    #[test]
+    fn optional_fields() {
+        let stpp = XMLSubtitleSampleEntry {
+            namespace: "http://www.w3.org/ns/ttml".into(),
+            ..Default::default()
+        };
+        let data = write(&stpp);
+        assert_eq!(data.len(), 16 + 26);
+        assert!(data.ends_with(b"ttml\0"));
+        let back = read::<XMLSubtitleSampleEntry>(&data);
+        assert_eq!((back.schema_location.as_ref(), back.bit_rate_box.as_ref()), (None, None));
+        assert_eq!(back, stpp);
+
+        let stpp = XMLSubtitleSampleEntry {
+            schema_location: Some("".into()),
+            bit_rate_box: Some(BitRateBox { avg_bitrate: 1000, ..Default::default() }),
+            ..stpp
+        };
+        let data = write(&stpp);
+        assert_eq!(data.len(), 16 + 26 + 1 + 20);
+        let back = read::<XMLSubtitleSampleEntry>(&data);
+        assert_eq!(back.auxiliary_mime_types, None);
+        assert_eq!(back, stpp);
     }
 }
-
@@ -0,0 +1,92 @@
+// Times in boxes are in seconds since 1904-01-01 00:00 UTC.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::spec::{MediaHeaderBox, MovieHeaderBox, TrackHeaderBox};
+
+// Seconds from 1904-01-01 to 1970-01-01.
+const SECONDS_1904_TO_1970: u64 = 2_082_844_800;
+
+/// The time for a number of seconds since 1904-01-01 00:00 UTC, like
+/// the `creation_time` of a `mvhd` box. `None` if the time is too far
+/// away for a `SystemTime`.
+pub fn system_time(seconds_since_1904: u64) -> Option<SystemTime> {
+    let epoch = UNIX_EPOCH.checked_sub(Duration::from_secs(SECONDS_1904_TO_1970))?;
+    epoch.checked_add(Duration::from_secs(seconds_since_1904))
+}
+
+/// The number of seconds since 1904-01-01 00:00 UTC for a time, as in
+/// the `creation_time` of a `mvhd` box. Times before 1904 are 0.
+pub fn seconds_since_1904(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs().saturating_add(SECONDS_1904_TO_1970),
+        Err(e) => SECONDS_1904_TO_1970.saturating_sub(e.duration().as_secs()),
+    }
+}
+
+// `created` and `modified` for boxes with `creation_time` and `modification_time`.
+macro_rules! box_times {
+    ($($name:ident,)*) => {
+        $(
+            impl $name {
+                /// The `creation_time`, if a `SystemTime` can hold it.
+                pub fn created(&self) -> Option<SystemTime> {
+                    system_time(self.creation_time)
+                }
+
+                /// The `modification_time`, if a `SystemTime` can hold it.
+                pub fn modified(&self) -> Option<SystemTime> {
+                    system_time(self.modification_time)
+                }
+
+                /// Set the `creation_time`, builder style. Raises the
+                /// version if the time does not fit in 32 bits.
+                pub fn with_created(self, time: SystemTime) -> $name {
+                    self.with_creation_time(seconds_since_1904(time))
+                }
+
+                /// Set the `modification_time`, builder style. Raises the
+                /// version if the time does not fit in 32 bits.
+                pub fn with_modified(self, time: SystemTime) -> $name {
+                    self.with_modification_time(seconds_since_1904(time))
+                }
+            }
+        )*
+    };
+}
+
+box_times! {
+    MovieHeaderBox,
+    TrackHeaderBox,
+    MediaHeaderBox,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(system_time(SECONDS_1904_TO_1970), Some(UNIX_EPOCH));
+        assert_eq!(seconds_since_1904(UNIX_EPOCH), SECONDS_1904_TO_1970);
+        let t = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(system_time(seconds_since_1904(t)), Some(t));
+        assert_eq!(seconds_since_1904(UNIX_EPOCH - Duration::from_secs(SECONDS_1904_TO_1970 + 1)), 0);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(system_time(u64::MAX), None);
+        let mvhd = MovieHeaderBox { creation_time: u64::MAX, ..Default::default() };
+        assert_eq!(mvhd.created(), None);
+        assert!(mvhd.modified().is_some());
+    }
+
+    #[test]
+    fn with_created() {
+        // 2040 does not fit in 32 bits.
+        let t = UNIX_EPOCH + Duration::from_secs(2_220_000_000);
+        let mvhd = MovieHeaderBox::default().with_created(t);
+        assert_eq!(mvhd.version, 1);
+        assert_eq!(mvhd.created(), Some(t));
+    }
+}
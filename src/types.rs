@@ -0,0 +1,238 @@
+// Basic types used by the generated code.
+use std::fmt;
+use std::ops::Deref;
+
+// The 24 bits of flags in the header of a `FullBox`.
+pub type Flags = u32;
+
+/// A four character code, like the type of a box.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FourCC(pub u32);
+
+impl FourCC {
+    /// FourCC from 4 bytes, as in `FourCC::new(b"moov")`.
+    pub const fn new(b: &[u8; 4]) -> FourCC {
+        FourCC(((b[0] as u32) << 24) | ((b[1] as u32) << 16) | ((b[2] as u32) << 8) | (b[3] as u32))
+    }
+
+    /// The 4 bytes of this FourCC.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl From<u32> for FourCC {
+    fn from(v: u32) -> FourCC {
+        FourCC(v)
+    }
+}
+
+impl From<&[u8; 4]> for FourCC {
+    fn from(b: &[u8; 4]) -> FourCC {
+        FourCC::new(b)
+    }
+}
+
+impl fmt::Display for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in &self.to_bytes() {
+            if b.is_ascii_graphic() || b == b' ' {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// A 16 byte UUID, like the extended type of a `uuid` box.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid(pub [u8; 16]);
+
+impl From<[u8; 16]> for Uuid {
+    fn from(b: [u8; 16]) -> Uuid {
+        Uuid(b)
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// How a `string` is encoded in a box.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StringEncoding {
+    /// UTF-8, the default.
+    #[default]
+    Utf8,
+    /// UTF-16, big endian, after a byte order mark.
+    Utf16BE,
+    /// UTF-16, little endian, after a byte order mark.
+    Utf16LE,
+}
+
+/// A `string` field: null-terminated UTF-8, or UTF-16 with a byte order mark.
+///
+/// The encoding, and whether the null byte was missing at the end of
+/// the box, are remembered so that the string is written back exactly
+/// as it was read. A new string is UTF-8, with a null byte.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BoxString {
+    pub value:          String,
+    pub encoding:       StringEncoding,
+    pub unterminated:   bool,
+}
+
+impl BoxString {
+    /// New UTF-8 string.
+    pub fn new(value: impl Into<String>) -> BoxString {
+        BoxString {
+            value: value.into(),
+            ..BoxString::default()
+        }
+    }
+
+    /// The string.
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+}
+
+impl Deref for BoxString {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.value
+    }
+}
+
+impl From<&str> for BoxString {
+    fn from(s: &str) -> BoxString {
+        BoxString::new(s)
+    }
+}
+
+impl From<String> for BoxString {
+    fn from(s: String) -> BoxString {
+        BoxString::new(s)
+    }
+}
+
+impl From<BoxString> for String {
+    fn from(s: BoxString) -> String {
+        s.value
+    }
+}
+
+impl PartialEq<str> for BoxString {
+    fn eq(&self, other: &str) -> bool {
+        self.value == other
+    }
+}
+
+impl PartialEq<&str> for BoxString {
+    fn eq(&self, other: &&str) -> bool {
+        self.value == *other
+    }
+}
+
+impl fmt::Display for BoxString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl fmt::Debug for BoxString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.value, f)
+    }
+}
+
+/// A string in a field of a fixed size, where the first byte is the
+/// length of the string, like `compressorname`.
+///
+/// The bytes of the field are kept as they were read, with a length
+/// that may not fit and the padding after the string, so that they
+/// are written back exactly. A new string is padded with zeros.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FixedString(pub Vec<u8>);
+
+impl FixedString {
+    /// New string. It must fit in the field when it is written.
+    pub fn new(value: &str) -> FixedString {
+        let mut raw = vec![ value.len().min(255) as u8 ];
+        raw.extend_from_slice(value.as_bytes());
+        FixedString(raw)
+    }
+
+    /// The bytes of the string, without the length and the padding.
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.first().cloned().unwrap_or(0) as usize;
+        &self.0[1.min(self.0.len()) .. std::cmp::min(1 + len, self.0.len())]
+    }
+
+    /// The string, with invalid UTF-8 replaced.
+    pub fn to_string_lossy(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(self.as_bytes())
+    }
+}
+
+impl From<&str> for FixedString {
+    fn from(s: &str) -> FixedString {
+        FixedString::new(s)
+    }
+}
+
+impl fmt::Display for FixedString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl fmt::Debug for FixedString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+/// How the size of a box is written in its header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SizeForm {
+    /// A 32-bit size, or a 64-bit `largesize` if the box is too big for that.
+    #[default]
+    Normal,
+    /// A 64-bit `largesize`, even if the box is small.
+    Large,
+    /// A size of 0: the box extends to the end of the file. This is
+    /// only right for the last box.
+    ToEnd,
+}
+
+/// A box that is kept as-is: its type and its contents.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RawBox {
+    pub fourcc: FourCC,
+    pub data:   Vec<u8>,
+    /// How the size is written, as it was read.
+    pub size_form: SizeForm,
+}
@@ -0,0 +1,290 @@
+// Writing boxes to a buffer.
+use std::io;
+
+use crate::context::Context;
+use crate::reader::invalid_data;
+use crate::types::{BoxString, FixedString, FourCC, RawBox, SizeForm, StringEncoding, Uuid};
+
+/// Types that can be written to a `BoxWriter`.
+///
+/// This is implemented by `def_box!` for every class.
+pub trait WriteTo {
+    fn write_to(&self, w: &mut BoxWriter) -> io::Result<()>;
+}
+
+/// Writes boxes, and the fields inside them, to a buffer.
+///
+/// Fields do not have to be a multiple of 8 bits, they are
+/// packed most significant bit first.
+#[derive(Clone, Debug, Default)]
+pub struct BoxWriter {
+    data:   Vec<u8>,
+    // bits that do not make up a whole byte yet.
+    bits:   u8,
+    nbits:  u32,
+    // values known to the box being written, and the boxes in it.
+    ctx:    Context,
+    // the values of the enclosing boxes, restored by `end_box`.
+    scopes: Vec<Context>,
+    // how the sizes of the open boxes are written.
+    sizes:  Vec<SizeForm>,
+}
+
+impl BoxWriter {
+    /// New, empty writer.
+    pub fn new() -> BoxWriter {
+        BoxWriter::default()
+    }
+
+    /// The data written so far. If the last field did not end on a
+    /// byte boundary, the bits of the last, partial byte are not included.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Return the data that was written.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Check that we are at a multiple of `bits` bits.
+    pub fn check_aligned(&self, bits: u32) -> io::Result<()> {
+        let bitpos = self.data.len() as u64 * 8 + self.nbits as u64;
+        if bits > 0 && !bitpos.is_multiple_of(bits as u64) {
+            return Err(invalid_data(format!("position is not aligned({})", bits)));
+        }
+        Ok(())
+    }
+
+    /// Current position.
+    pub fn position(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Write raw bytes.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.check_aligned(8)?;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Write an unsigned integer of `bits` bits.
+    pub fn write_uint(&mut self, bits: u32, value: u64) -> io::Result<()> {
+        if bits > 64 {
+            return Err(io::Error::other(format!("cannot write int({})", bits)));
+        }
+        if bits < 64 && value >> bits != 0 {
+            return Err(invalid_data(format!("value {} does not fit in {} bits", value, bits)));
+        }
+        if self.nbits == 0 && bits.is_multiple_of(8) {
+            let bytes = value.to_be_bytes();
+            return self.write(&bytes[(8 - bits / 8) as usize ..]);
+        }
+        let mut todo = bits;
+        while todo > 0 {
+            let n = std::cmp::min(8 - self.nbits, todo);
+            let b = (value >> (todo - n)) & ((1 << n) - 1);
+            self.bits = ((self.bits as u64) << n | b) as u8;
+            self.nbits += n;
+            todo -= n;
+            if self.nbits == 8 {
+                self.data.push(self.bits);
+                self.bits = 0;
+                self.nbits = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a signed integer of `bits` bits.
+    pub fn write_int(&mut self, bits: u32, value: i64) -> io::Result<()> {
+        if bits > 0 && bits < 64 {
+            let max = (1i64 << (bits - 1)) - 1;
+            if value > max || value < -max - 1 {
+                return Err(invalid_data(format!("value {} does not fit in {} bits", value, bits)));
+            }
+            let mask = (1u64 << bits) - 1;
+            return self.write_uint(bits, value as u64 & mask);
+        }
+        self.write_uint(bits, value as u64)
+    }
+
+    /// Write a string in the encoding it was read in. The null at the
+    /// end is left out if it was missing when the string was read.
+    pub fn write_string(&mut self, value: &BoxString) -> io::Result<()> {
+        if value.contains('\0') {
+            return Err(invalid_data("string contains a null byte"));
+        }
+        match value.encoding {
+            StringEncoding::Utf8 => {
+                self.write(value.as_bytes())?;
+                if !value.unterminated {
+                    self.write(&[0])?;
+                }
+            },
+            StringEncoding::Utf16BE | StringEncoding::Utf16LE => {
+                let be = value.encoding == StringEncoding::Utf16BE;
+                let unit = |u: u16| if be { u.to_be_bytes() } else { u.to_le_bytes() };
+                self.write(&unit(0xfeff))?;
+                for u in value.encode_utf16() {
+                    self.write(&unit(u))?;
+                }
+                if !value.unterminated {
+                    self.write(&[0, 0])?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Write a string in a field of a fixed size, where the first byte
+    /// is the length of the string (like `compressorname`). The field
+    /// is padded with zeros.
+    pub fn write_fixed_string(&mut self, size: usize, value: &FixedString) -> io::Result<()> {
+        let raw = &value.0;
+        if raw.len() > size {
+            return Err(invalid_data(format!("string {:?} does not fit in {} bytes", value, size)));
+        }
+        self.write(raw)?;
+        self.write(&vec![0; size - raw.len()])
+    }
+
+    /// Start a box. Writes the header, with a size that is filled in by `end_box`.
+    pub fn begin_box(&mut self, fourcc: FourCC) -> io::Result<usize> {
+        self.begin_box_sized(fourcc, SizeForm::Normal)
+    }
+
+    /// Start a box, with the size written as `size_form` says.
+    pub fn begin_box_sized(&mut self, fourcc: FourCC, size_form: SizeForm) -> io::Result<usize> {
+        let start = self.position();
+        let size = if size_form == SizeForm::Large { 1 } else { 0 };
+        self.write_uint(32, size)?;
+        self.write_uint(32, fourcc.0 as u64)?;
+        if size_form == SizeForm::Large {
+            self.write_uint(64, 0)?;
+        }
+        self.scopes.push(self.ctx.clone());
+        self.sizes.push(size_form);
+        Ok(start)
+    }
+
+    /// Finish a box that was started with `begin_box`.
+    pub fn end_box(&mut self, start: usize) -> io::Result<()> {
+        self.check_aligned(8)?;
+        if let Some(ctx) = self.scopes.pop() {
+            self.ctx = ctx;
+        }
+        let size = (self.position() - start) as u64;
+        match self.sizes.pop().unwrap_or_default() {
+            SizeForm::Normal if size <= u32::MAX as u64 => {
+                self.data[start .. start + 4].copy_from_slice(&(size as u32).to_be_bytes());
+            },
+            SizeForm::Normal => {
+                // Does not fit, use a largesize after the fourcc.
+                let size = size + 8;
+                self.data[start .. start + 4].copy_from_slice(&1u32.to_be_bytes());
+                let pos = start + 8;
+                self.data.splice(pos .. pos, size.to_be_bytes().iter().cloned());
+            },
+            SizeForm::Large => {
+                self.data[start + 8 .. start + 16].copy_from_slice(&size.to_be_bytes());
+            },
+            // The size stays 0.
+            SizeForm::ToEnd => {},
+        }
+        Ok(())
+    }
+
+    /// Check that the number of entries in a list is what a
+    /// count field, or the version of a box, says it is.
+    pub fn check_count(&self, name: &str, len: usize, count: i64) -> io::Result<()> {
+        if len as i64 != count {
+            return Err(invalid_data(format!("{}: has {} entries, expected {}", name, len, count)));
+        }
+        Ok(())
+    }
+
+    /// Make a value known to the rest of this box, and the boxes in it.
+    pub fn set_var(&mut self, name: &str, value: i64) {
+        self.ctx.set(name, value);
+    }
+
+    /// Value of a variable that is not part of the class that
+    /// is being written, like a field of an enclosing class.
+    pub fn lookup(&self, name: &str) -> io::Result<i64> {
+        self.ctx.lookup(name)
+    }
+}
+
+impl WriteTo for RawBox {
+    fn write_to(&self, w: &mut BoxWriter) -> io::Result<()> {
+        let start = w.begin_box_sized(self.fourcc, self.size_form)?;
+        w.write(&self.data)?;
+        w.end_box(start)
+    }
+}
+
+impl WriteTo for Uuid {
+    fn write_to(&self, w: &mut BoxWriter) -> io::Result<()> {
+        w.write(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::BoxReader;
+
+    #[test]
+    fn write_bits() {
+        // bit(1) + int(31), then int(5)[3] and a bit(1): 10001 00011 11111 1.
+        let mut w = BoxWriter::new();
+        w.write_uint(1, 1).unwrap();
+        w.write_uint(31, 42).unwrap();
+        for v in [ 17, 3, 31 ] {
+            w.write_uint(5, v).unwrap();
+        }
+        w.write_uint(1, 1).unwrap();
+        assert_eq!(w.into_inner(), [ 0x80, 0, 0, 0x2a, 0x88, 0xff ]);
+    }
+
+    #[test]
+    fn write_read() {
+        // (bits, signed, value), 184 bits in all.
+        let fields: &[(u32, bool, i64)] = &[
+            (1, false, 1), (31, false, 0x7fff_ffff), (5, false, 17), (5, false, 0), (5, false, 31),
+            (12, true, -1), (12, true, -2048), (12, true, 2047), (4, true, -8), (64, true, i64::MIN),
+            (3, true, -3), (13, true, -4096), (16, false, 0x1234), (1, false, 0),
+        ];
+        let mut w = BoxWriter::new();
+        for &(bits, signed, v) in fields {
+            match signed {
+                true => w.write_int(bits, v).unwrap(),
+                false => w.write_uint(bits, v as u64).unwrap(),
+            }
+        }
+        let data = w.into_inner();
+        assert_eq!(data.len(), 23);
+        let mut r = BoxReader::new(&data);
+        for &(bits, signed, v) in fields {
+            match signed {
+                true => assert_eq!(r.read_int(bits).unwrap(), v),
+                false => assert_eq!(r.read_uint(bits).unwrap(), v as u64),
+            }
+        }
+        assert_eq!(r.left(), 0);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut w = BoxWriter::new();
+        assert!(w.write_uint(3, 8).is_err());
+        assert!(w.write_uint(65, 0).is_err());
+        assert!(w.write_int(12, 2048).is_err());
+        assert!(w.write_int(12, -2049).is_err());
+        assert!(w.write_int(1, 1).is_err());
+        // nothing was written.
+        assert_eq!(w.position(), 0);
+        assert!(w.check_aligned(8).is_ok());
+    }
+}
@@ -0,0 +1,6 @@
+// The errors of def_box!, which must point at the right token.
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/compile_fail/*.rs");
+}
@@ -0,0 +1,9 @@
+use iso_bmff::*;
+
+def_box! {
+    class WideBox extends Box("wide") {
+       bit(65) value;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use iso_bmff::*;
+
+def_box! {
+    class WideBox extends Box("wide") {
+       unsigned int(128) value;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use iso_bmff::*;
+
+def_box! {
+    class LargeBox extends Box("larg") {
+       unsigned int(64) value = 0x1_0000_0000_0000_0000;
+    }
+}
+
+fn main() {}
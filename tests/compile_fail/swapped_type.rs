@@ -0,0 +1,9 @@
+use iso_bmff::*;
+
+def_box! {
+    class LoudnessBox extends Box("ldns") {
+       loudness TrackLoudnessInfo[];
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use iso_bmff::*;
+
+def_box! {
+    class MyEntry() extends NoSuchClass("mine") {
+       unsigned int(8) value;
+    }
+}
+
+fn main() {}
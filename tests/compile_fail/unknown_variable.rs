@@ -0,0 +1,11 @@
+use iso_bmff::*;
+
+def_box! {
+    class CountBox extends Box("cnts") {
+       for (i = 0; i < entry_count; i++) {
+          unsigned int(8) value;
+       }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,536 @@
+// Read and write back whole files, which must come out byte for byte the same.
+use iso_bmff::*;
+
+fn bx(fourcc: &[u8; 4], parts: &[&[u8]]) -> Vec<u8> {
+    let body = parts.concat();
+    let mut v = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    v.extend_from_slice(fourcc);
+    v.extend(body);
+    v
+}
+
+fn full(fourcc: &[u8; 4], version: u8, flags: u32, parts: &[&[u8]]) -> Vec<u8> {
+    let mut vf = flags.to_be_bytes();
+    vf[0] = version;
+    let mut all: Vec<&[u8]> = vec![ &vf ];
+    all.extend_from_slice(parts);
+    bx(fourcc, &all)
+}
+
+const UNITY: [u8; 36] = [
+    0, 1, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,
+    0, 0, 0, 0,  0, 1, 0, 0,  0, 0, 0, 0,
+    0, 0, 0, 0,  0, 0, 0, 0,  0x40, 0, 0, 0,
+];
+
+// The box at `path`, like [ b"moov", b"trak" ].
+fn find<'a>(boxes: &'a [BoxNode], path: &[&[u8; 4]]) -> Option<&'a BoxNode> {
+    let b = boxes.iter().find(|b| b.fourcc() == FourCC::new(path[0]))?;
+    match path.len() {
+        1 => Some(b),
+        _ => find(b.children()?, &path[1..]),
+    }
+}
+
+fn roundtrip(data: &[u8]) -> Vec<BoxNode> {
+    let boxes = read_boxes(data).unwrap();
+    assert_eq!(write_boxes(&boxes).unwrap(), data);
+    boxes
+}
+
+fn ftyp_box() -> Vec<u8> {
+    bx(b"ftyp", &[ b"isom", &[0, 0, 2, 0], b"isomiso2avc1mp41" ])
+}
+
+fn moov_box() -> Vec<u8> {
+    let mvhd = full(b"mvhd", 0, 0, &[
+        &[0; 8], &1000u32.to_be_bytes(), &3000u32.to_be_bytes(),
+        &[0, 1, 0, 0], &[1, 0], &[0; 10], &UNITY, &[0; 24], &2u32.to_be_bytes(),
+    ]);
+    let tkhd = full(b"tkhd", 0, 3, &[
+        &[0; 8], &1u32.to_be_bytes(), &[0; 4], &3000u32.to_be_bytes(),
+        &[0; 8], &[0; 4], &[0; 4], &UNITY, &[0x07, 0x80, 0, 0], &[0x04, 0x38, 0, 0],
+    ]);
+    let mdhd = full(b"mdhd", 0, 0, &[
+        &[0; 8], &90000u32.to_be_bytes(), &270000u32.to_be_bytes(), &[0x15, 0xc7], &[0; 2],
+    ]);
+    let hdlr = full(b"hdlr", 0, 0, &[ &[0; 4], b"vide", &[0; 12], b"VideoHandler\0" ]);
+    let vmhd = full(b"vmhd", 0, 1, &[ &[0; 8] ]);
+    let dinf = bx(b"dinf", &[ &full(b"dref", 0, 0, &[ &1u32.to_be_bytes(), &full(b"url ", 0, 1, &[]) ]) ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &bx(b"mp4v", &[ &[0; 6], &[0, 1] ]) ]);
+    let stts = full(b"stts", 0, 0, &[ &1u32.to_be_bytes(), &3u32.to_be_bytes(), &3000u32.to_be_bytes() ]);
+    let stsc = full(b"stsc", 0, 0, &[ &1u32.to_be_bytes(), &1u32.to_be_bytes(), &3u32.to_be_bytes(), &1u32.to_be_bytes() ]);
+    let stco = full(b"stco", 0, 0, &[ &1u32.to_be_bytes(), &48u32.to_be_bytes() ]);
+    let stbl = bx(b"stbl", &[ &stsd, &stts, &stsc, &stco ]);
+    let minf = bx(b"minf", &[ &vmhd, &dinf, &stbl ]);
+    let mdia = bx(b"mdia", &[ &mdhd, &hdlr, &minf ]);
+    let trak = bx(b"trak", &[ &tkhd, &mdia ]);
+    bx(b"moov", &[ &mvhd, &trak ])
+}
+
+fn moof_box() -> Vec<u8> {
+    let mfhd = full(b"mfhd", 0, 0, &[ &1u32.to_be_bytes() ]);
+    let tfhd = full(b"tfhd", 0, 0x020000, &[ &1u32.to_be_bytes() ]);
+    // Version 1, although the time would fit in version 0.
+    let tfdt = full(b"tfdt", 1, 0, &[ &100u64.to_be_bytes() ]);
+    let trun = full(b"trun", 0, 0x000301, &[
+        &2u32.to_be_bytes(), &112u32.to_be_bytes(),
+        &1024u32.to_be_bytes(), &10u32.to_be_bytes(),
+        &1024u32.to_be_bytes(), &20u32.to_be_bytes(),
+    ]);
+    let traf = bx(b"traf", &[ &tfhd, &tfdt, &trun ]);
+    bx(b"moof", &[ &mfhd, &traf ])
+}
+
+#[test]
+fn ftyp_moov() {
+    let data = [ ftyp_box(), moov_box() ].concat();
+    let boxes = roundtrip(&data);
+    assert!(matches!(boxes[0], BoxNode::FileTypeBox(_)));
+    assert!(matches!(find(&boxes, &[ b"moov", b"mvhd" ]), Some(BoxNode::MovieHeaderBox(_))));
+    let path: &[&[u8; 4]] = &[ b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco" ];
+    assert!(matches!(find(&boxes, path), Some(BoxNode::ChunkOffsetBox(_))));
+}
+
+#[test]
+fn moof() {
+    let data = [ moof_box(), bx(b"mdat", &[ &[1, 2, 3, 4] ]) ].concat();
+    let boxes = roundtrip(&data);
+    match find(&boxes, &[ b"moof", b"traf", b"tfdt" ]) {
+        Some(BoxNode::TrackFragmentBaseMediaDecodeTimeBox(tfdt)) => assert_eq!(tfdt.version, 1),
+        other => panic!("tfdt: {:?}", other),
+    }
+    match find(&boxes, &[ b"moof", b"traf", b"trun" ]) {
+        Some(BoxNode::TrackRunBox(trun)) => assert_eq!(trun.samples.len(), 2),
+        other => panic!("trun: {:?}", other),
+    }
+}
+
+#[test]
+fn largesize() {
+    // A small mdat with a 64-bit size.
+    let mut mdat = vec![ 0, 0, 0, 1 ];
+    mdat.extend_from_slice(b"mdat");
+    mdat.extend_from_slice(&20u64.to_be_bytes());
+    mdat.extend_from_slice(&[1, 2, 3, 4]);
+    let data = [ ftyp_box(), moof_box(), mdat ].concat();
+    let boxes = roundtrip(&data);
+    match boxes[2] {
+        BoxNode::MediaDataBox(ref mdat) => {
+            assert_eq!(mdat.size_form, SizeForm::Large);
+            assert_eq!(mdat.data, [1, 2, 3, 4]);
+        },
+        ref other => panic!("mdat: {:?}", other),
+    }
+
+    // An unknown box keeps it as well.
+    let mut free = vec![ 0, 0, 0, 1 ];
+    free.extend_from_slice(b"xxxx");
+    free.extend_from_slice(&16u64.to_be_bytes());
+    roundtrip(&free);
+}
+
+#[test]
+fn size_to_end() {
+    // The last box can have a size of 0, up to the end of the file.
+    let mut mdat = vec![ 0, 0, 0, 0 ];
+    mdat.extend_from_slice(b"mdat");
+    mdat.extend_from_slice(&[1, 2, 3, 4]);
+    let data = [ ftyp_box(), moov_box(), mdat ].concat();
+    let boxes = roundtrip(&data);
+    match boxes[2] {
+        BoxNode::MediaDataBox(ref mdat) => {
+            assert_eq!(mdat.size_form, SizeForm::ToEnd);
+            assert_eq!(mdat.data, [1, 2, 3, 4]);
+        },
+        ref other => panic!("mdat: {:?}", other),
+    }
+}
+
+#[test]
+fn child_order() {
+    // `idat` before `pitm`, which the spec declares the other way round.
+    let hdlr = full(b"hdlr", 0, 0, &[ &[0; 4], b"pict", &[0; 12], b"\0" ]);
+    let idat = bx(b"idat", &[ &[1, 2, 3] ]);
+    let pitm = full(b"pitm", 0, 0, &[ &[0, 1] ]);
+    let boxes = roundtrip(&full(b"meta", 0, 0, &[ &hdlr, &idat, &pitm ]));
+    match boxes[0] {
+        BoxNode::MetaBox(ref meta) => {
+            assert!(meta.item_data.is_some() && meta.primary_resource.is_some());
+            assert!(!meta.child_order.is_empty());
+        },
+        ref other => panic!("meta: {:?}", other),
+    }
+
+    // In the order of the declarations there is nothing to keep.
+    let boxes = roundtrip(&full(b"meta", 0, 0, &[ &hdlr, &pitm, &idat ]));
+    match boxes[0] {
+        BoxNode::MetaBox(ref meta) => assert!(meta.child_order.is_empty()),
+        ref other => panic!("meta: {:?}", other),
+    }
+}
+
+#[test]
+fn variable_fourcc() {
+    let free = bx(b"free", &[ &[0; 4] ]);
+    let skip = bx(b"skip", &[]);
+    let boxes = roundtrip(&[ free, skip ].concat());
+    assert!(matches!(boxes[0], BoxNode::FreeSpaceBox(_)));
+    assert!(matches!(boxes[1], BoxNode::FreeSpaceBox(_)));
+    assert_eq!(boxes[1].fourcc(), FourCC::new(b"skip"));
+
+    // The boxes in a `tref` have the type of the reference as fourcc.
+    let tref = bx(b"tref", &[ &bx(b"hint", &[ &2u32.to_be_bytes() ]) ]);
+    let boxes = roundtrip(&bx(b"trak", &[ &tref ]));
+    match find(&boxes, &[ b"trak", b"tref", b"hint" ]) {
+        Some(BoxNode::TrackReferenceTypeBox(r)) => assert_eq!(FourCC(r.reference_type), FourCC::new(b"hint")),
+        other => panic!("hint: {:?}", other),
+    }
+    // But not elsewhere.
+    let boxes = roundtrip(&bx(b"hint", &[ &2u32.to_be_bytes() ]));
+    assert!(matches!(boxes[0], BoxNode::Unknown{ .. }));
+}
+
+// A track with a handler, and a box in its `stbl`.
+fn track(handler: &[u8; 4], stbl: &[u8]) -> Vec<u8> {
+    let hdlr = full(b"hdlr", 0, 0, &[ &[0; 4], handler, &[0; 12], b"\0" ]);
+    let minf = bx(b"minf", &[ &bx(b"stbl", &[ stbl ]) ]);
+    bx(b"trak", &[ &bx(b"mdia", &[ &hdlr, &minf ]) ])
+}
+
+fn stsd_entry(boxes: &[BoxNode]) -> &BoxNode {
+    match find(boxes, &[ b"trak", b"mdia", b"minf", b"stbl", b"stsd" ]) {
+        Some(BoxNode::SampleDescriptionBox(stsd)) => &stsd.sample_entry[0],
+        other => panic!("stsd: {:?}", other),
+    }
+}
+
+#[test]
+fn sample_entries() {
+    let avc1 = bx(b"avc1", &[
+        &[0; 6], &[0, 1], &[0; 16], &[0x07, 0x80, 0x04, 0x38],
+        &[0, 0x48, 0, 0, 0, 0x48, 0, 0], &[0; 4], &[0, 1], &[0; 32], &[0, 0x18], &[0xff, 0xff],
+        &bx(b"avcC", &[ &[1, 0x64, 0, 0x28] ]),
+        &bx(b"pasp", &[ &1u32.to_be_bytes(), &1u32.to_be_bytes() ]),
+    ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &avc1 ]);
+    let boxes = roundtrip(&track(b"vide", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::VisualSampleEntry(e) => {
+            assert_eq!(FourCC(e.codingname), FourCC::new(b"avc1"));
+            assert_eq!((e.width, e.height), (1920, 1080));
+            assert!(e.pasp.is_some());
+        },
+        other => panic!("avc1: {:?}", other),
+    }
+
+    // The same entry in a sound track is not a `VisualSampleEntry`.
+    let boxes = roundtrip(&track(b"soun", &stsd));
+    assert!(matches!(stsd_entry(&boxes), BoxNode::Unknown{ .. }));
+
+    // Audio, where version 1 has an `entry_version` instead of a reserved 0.
+    let mp4a = |version: u8| bx(b"mp4a", &[
+        &[0; 6], &[0, 1], &[0, version], &[0; 6], &[0, 2], &[0, 16], &[0; 4], &[0xbb, 0x80, 0, 0],
+    ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &mp4a(0) ]);
+    let boxes = roundtrip(&track(b"soun", &stsd));
+    assert!(matches!(stsd_entry(&boxes), BoxNode::AudioSampleEntry(_)));
+    let stsd = full(b"stsd", 1, 0, &[ &1u32.to_be_bytes(), &mp4a(1) ]);
+    let boxes = roundtrip(&track(b"soun", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::AudioSampleEntryV1(e) => assert_eq!(e.entry_version, 1),
+        other => panic!("mp4a: {:?}", other),
+    }
+}
+
+#[test]
+fn dirty_compressorname() {
+    // A length that is too big, bytes that are not UTF-8, and junk after the string.
+    let mut name = vec![ 40, b'a', b'b', 0xff ];
+    name.extend_from_slice(b"junk after the name.........");
+    let avc1 = bx(b"avc1", &[
+        &[0; 6], &[0, 1], &[0; 16], &[0x07, 0x80, 0x04, 0x38],
+        &[0, 0x48, 0, 0, 0, 0x48, 0, 0], &[0; 4], &[0, 1], &name, &[0, 0x18], &[0xff, 0xff],
+    ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &avc1 ]);
+    let boxes = roundtrip(&track(b"vide", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::VisualSampleEntry(e) => assert!(e.compressorname.to_string_lossy().starts_with("ab\u{fffd}junk")),
+        other => panic!("avc1: {:?}", other),
+    }
+
+    // A new name is padded with zeros.
+    let mut w = BoxWriter::new();
+    w.write_fixed_string(8, &FixedString::new("avc")).unwrap();
+    assert_eq!(w.into_inner(), [ 3, b'a', b'v', b'c', 0, 0, 0, 0 ]);
+    assert!(BoxWriter::new().write_fixed_string(3, &FixedString::new("avc")).is_err());
+}
+
+#[test]
+fn sample_group_entries() {
+    // Version 1 with a default_length, a "roll" and a "tele" group.
+    let roll = full(b"sgpd", 1, 0, &[
+        b"roll", &2u32.to_be_bytes(), &2u32.to_be_bytes(), &[0xff, 0xfe], &[0, 3],
+    ]);
+    let boxes = roundtrip(&track(b"soun", &roll));
+    match find(&boxes, &[ b"trak", b"mdia", b"minf", b"stbl", b"sgpd" ]) {
+        Some(BoxNode::SampleGroupDescriptionBox(sgpd)) => {
+            let entries: Vec<_> = sgpd.entries.iter().map(|e| &e.sample_group_description_entry).collect();
+            match entries[..] {
+                [ SampleGroupEntry::AudioRollRecoveryEntry(a), SampleGroupEntry::AudioRollRecoveryEntry(b) ] => {
+                    assert_eq!((a.roll_distance, b.roll_distance), (-2, 3));
+                },
+                _ => panic!("roll: {:?}", entries),
+            }
+        },
+        other => panic!("sgpd: {:?}", other),
+    }
+
+    // A grouping type that is not known is kept as is.
+    let xxxx = full(b"sgpd", 1, 0, &[ b"xxxx", &3u32.to_be_bytes(), &1u32.to_be_bytes(), &[1, 2, 3] ]);
+    let boxes = roundtrip(&track(b"vide", &xxxx));
+    match find(&boxes, &[ b"trak", b"mdia", b"minf", b"stbl", b"sgpd" ]) {
+        Some(BoxNode::SampleGroupDescriptionBox(sgpd)) => {
+            assert!(matches!(sgpd.entries[0].sample_group_description_entry, SampleGroupEntry::Unknown(_)));
+        },
+        other => panic!("sgpd: {:?}", other),
+    }
+}
+
+#[test]
+fn sample_group_lengths() {
+    // Version 1 with a description_length per entry. An "alst" entry
+    // runs to the end of its description, not to the end of the box.
+    let alst = |outputs: &[u8]| {
+        let mut e = [ &[0, 1][..], &[0, 0], &7u32.to_be_bytes() ].concat();
+        e.extend_from_slice(outputs);
+        [ &(e.len() as u32).to_be_bytes()[..], &e ].concat()
+    };
+    let sgpd = full(b"sgpd", 1, 0, &[
+        b"alst", &0u32.to_be_bytes(), &2u32.to_be_bytes(), &alst(&[0, 1, 0, 2]), &alst(&[0, 3, 0, 4, 0, 5, 0, 6]),
+    ]);
+    let boxes = roundtrip(&track(b"vide", &sgpd));
+    match find(&boxes, &[ b"trak", b"mdia", b"minf", b"stbl", b"sgpd" ]) {
+        Some(BoxNode::SampleGroupDescriptionBox(sgpd)) => {
+            let entries: Vec<_> = sgpd.entries.iter().map(|e| &e.sample_group_description_entry).collect();
+            match entries[..] {
+                [ SampleGroupEntry::AlternativeStartupEntry(a), SampleGroupEntry::AlternativeStartupEntry(b) ] => {
+                    assert_eq!((a.entries.len(), b.entries.len()), (1, 2));
+                },
+                _ => panic!("alst: {:?}", entries),
+            }
+        },
+        other => panic!("sgpd: {:?}", other),
+    }
+}
+
+// A sample entry of another crate, that extends one of this crate.
+def_box! {
+    class XvidSampleEntry() extends VisualSampleEntry("xvid") {
+    }
+}
+
+#[test]
+fn extended_sample_entry() {
+    register_box::<XvidSampleEntry>();
+    let xvid = bx(b"xvid", &[
+        &[0; 6], &[0, 1], &[0; 16], &[0x02, 0x80, 0x01, 0xe0],
+        &[0, 0x48, 0, 0, 0, 0x48, 0, 0], &[0; 4], &[0, 1], &[0; 32], &[0, 0x18], &[0xff, 0xff],
+    ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &xvid ]);
+    let boxes = roundtrip(&track(b"vide", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::User(b) => {
+            let e = b.downcast_ref::<XvidSampleEntry>().unwrap();
+            assert_eq!((e.width, e.height), (640, 480));
+        },
+        other => panic!("xvid: {:?}", other),
+    }
+}
+
+// Registered boxes in a registered box.
+def_box! {
+    class OuterBox extends Box("xout") {
+    }
+}
+
+def_box! {
+    class InnerBox extends Box("xinn") {
+        unsigned int(8) value;
+    }
+}
+
+#[test]
+fn nested_user_boxes() {
+    register_box::<OuterBox>();
+    register_box::<InnerBox>();
+    // Registering while boxes are read must not wait for the reader.
+    let registering = std::thread::spawn(|| {
+        for _ in 0 .. 1000 {
+            register_box::<InnerBox>();
+        }
+    });
+    let data = bx(b"xout", &[ &bx(b"xinn", &[ &[7] ]), &bx(b"xinn", &[ &[8] ]) ]);
+    for _ in 0 .. 1000 {
+        let boxes = roundtrip(&data);
+        let inner = find(&boxes, &[ b"xout", b"xinn" ]);
+        match inner {
+            Some(BoxNode::User(b)) => assert_eq!(b.downcast_ref::<InnerBox>().unwrap().value, 7),
+            other => panic!("xinn: {:?}", other),
+        }
+    }
+    registering.join().unwrap();
+}
+
+#[test]
+fn trun_without_sample_fields() {
+    // No per-sample fields, so the samples are empty.
+    let trun = full(b"trun", 0, 0, &[ &2u32.to_be_bytes() ]);
+    match &roundtrip(&trun)[0] {
+        BoxNode::TrackRunBox(trun) => assert_eq!(trun.samples.len(), 2),
+        other => panic!("trun: {:?}", other),
+    }
+
+    // 4 bytes that do not belong to any sample.
+    let trun = full(b"trun", 0, 0, &[ &2u32.to_be_bytes(), &[1, 2, 3, 4] ]);
+    assert!(matches!(roundtrip(&trun)[0], BoxNode::Unknown{ .. }));
+
+    // sdtp has no count of its own, it runs to the end of the box.
+    let sdtp = full(b"sdtp", 0, 0, &[ &[0x10, 0x20, 0x24] ]);
+    match &roundtrip(&sdtp)[0] {
+        BoxNode::SampleDependencyTypeBox(sdtp) => assert_eq!(sdtp.samples.len(), 3),
+        other => panic!("sdtp: {:?}", other),
+    }
+}
+
+fn handler_name(hdlr: &[u8]) -> BoxString {
+    match &roundtrip(hdlr)[0] {
+        BoxNode::HandlerBox(hdlr) => hdlr.name.clone(),
+        other => panic!("hdlr: {:?}", other),
+    }
+}
+
+#[test]
+fn strings() {
+    let hdlr = |name: &[u8]| full(b"hdlr", 0, 0, &[ &[0; 4], b"soun", &[0; 12], name ]);
+
+    let name = handler_name(&hdlr(b"Sound\0"));
+    assert_eq!((name.as_str(), name.encoding, name.unterminated), ("Sound", StringEncoding::Utf8, false));
+
+    // Many encoders leave out the null byte at the end of the box.
+    let name = handler_name(&hdlr(b"Sound"));
+    assert_eq!((name.as_str(), name.unterminated), ("Sound", true));
+    let name = handler_name(&hdlr(b""));
+    assert_eq!((name.as_str(), name.unterminated), ("", true));
+
+    // UTF-16, with a byte order mark.
+    let name = handler_name(&hdlr(&[ 0xfe, 0xff, 0, b'S', 0x00, 0xe9, 0, 0 ]));
+    assert_eq!((name.as_str(), name.encoding, name.unterminated), ("S\u{e9}", StringEncoding::Utf16BE, false));
+    let name = handler_name(&hdlr(&[ 0xff, 0xfe, b'S', 0, 0xe9, 0x00 ]));
+    assert_eq!((name.as_str(), name.encoding, name.unterminated), ("S\u{e9}", StringEncoding::Utf16LE, true));
+
+    // Half a UTF-16 unit.
+    assert!(matches!(read_boxes(&hdlr(&[ 0xfe, 0xff, 0, b'S', 0 ])).unwrap()[0], BoxNode::Unknown{ .. }));
+
+    // A new string is UTF-8 with a null byte.
+    let mut w = BoxWriter::new();
+    w.write_string(&BoxString::new("S\u{e9}")).unwrap();
+    assert_eq!(w.into_inner(), b"S\xc3\xa9\0");
+    assert!(BoxWriter::new().write_string(&BoxString::new("a\0b")).is_err());
+}
+
+#[test]
+fn to_end_arrays() {
+    let ftyp = bx(b"ftyp", &[ b"isom", &[0; 4], b"isomavc1" ]);
+    match &roundtrip(&ftyp)[0] {
+        BoxNode::FileTypeBox(ftyp) => assert_eq!(ftyp.compatible_brands.len(), 2),
+        other => panic!("ftyp: {:?}", other),
+    }
+    let ftyp = bx(b"ftyp", &[ b"isom", &[0; 4] ]);
+    match &roundtrip(&ftyp)[0] {
+        BoxNode::FileTypeBox(ftyp) => assert!(ftyp.compatible_brands.is_empty()),
+        other => panic!("ftyp: {:?}", other),
+    }
+
+    // Half a brand at the end is an error.
+    let ftyp = bx(b"ftyp", &[ b"isom", &[0; 4], b"isomav" ]);
+    assert!(FileTypeBox::read_from(&mut BoxReader::new(&ftyp)).is_err());
+    assert!(matches!(roundtrip(&ftyp)[0], BoxNode::Unknown{ .. }));
+
+    // Bytes can not be partial.
+    let free = bx(b"free", &[ &[1, 2, 3] ]);
+    match &roundtrip(&free)[0] {
+        BoxNode::FreeSpaceBox(free) => assert_eq!(free.data, [1, 2, 3]),
+        other => panic!("free: {:?}", other),
+    }
+
+    // The size follows the elements.
+    let tref = TrackReferenceTypeBox { reference_type: u32::from_be_bytes(*b"chap"), track_i_ds: vec![1, 2, 3], ..Default::default() };
+    let mut w = BoxWriter::new();
+    tref.write_to(&mut w).unwrap();
+    let data = w.into_inner();
+    assert_eq!(&data[..4], &20u32.to_be_bytes());
+    assert_eq!(TrackReferenceTypeBox::read_from(&mut BoxReader::new(&data)).unwrap(), tref);
+}
+
+#[test]
+fn channel_count_from_sample_entry() {
+    // Three channels, the last one at an explicit position.
+    let chnl = full(b"chnl", 0, 0, &[ &[1, 0], &[2, 1, 126, 0xff, 0xa6, 10] ]);
+    let mp4a = |channels: u8| bx(b"mp4a", &[
+        &[0; 6], &[0, 1], &[0; 8], &[0, channels], &[0, 16], &[0; 4], &[0xbb, 0x80, 0, 0], &chnl,
+    ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &mp4a(3) ]);
+    let boxes = roundtrip(&track(b"soun", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::AudioSampleEntry(e) => {
+            let chnl = e.channel_layout.as_ref().unwrap();
+            let positions: Vec<_> = chnl.channels.iter().map(|c| c.speaker_position).collect();
+            assert_eq!(positions, [2, 1, 126]);
+            assert_eq!((chnl.channels[2].azimuth, chnl.channels[2].elevation), (Some(-90), Some(10)));
+        },
+        other => panic!("mp4a: {:?}", other),
+    }
+
+    // With two channels, the last speaker is trailing data.
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &mp4a(2) ]);
+    let boxes = roundtrip(&track(b"soun", &stsd));
+    assert!(matches!(stsd_entry(&boxes), BoxNode::Unknown{ .. }));
+
+    // On its own, the number of channels is not known.
+    assert!(ChannelLayout::read_from(&mut BoxReader::new(&chnl)).is_err());
+}
+
+#[test]
+fn sample_entry_child_boxes() {
+    // DRC boxes in an audio sample entry.
+    let udc1 = full(b"udc1", 0, 0, &[ &[1, 2, 3] ]);
+    let free = bx(b"free", &[]);
+    let mp4a = bx(b"mp4a", &[
+        &[0; 6], &[0, 1], &[0; 8], &[0, 2], &[0, 16], &[0; 4], &[0xbb, 0x80, 0, 0], &udc1, &free,
+    ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &mp4a ]);
+    let boxes = roundtrip(&track(b"soun", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::AudioSampleEntry(e) => {
+            assert_eq!(e.drc_coefficients_basic.len(), 1);
+            assert_eq!(e.drc_coefficients_basic[0].data, [1, 2, 3]);
+            assert_eq!(e.boxes.len(), 1);
+        },
+        other => panic!("mp4a: {:?}", other),
+    }
+
+    // The boxes of a `MetaDataSampleEntry` come after the fields of
+    // the derived class.
+    let metx = bx(b"metx", &[ &[0; 6], &[0, 1], b"\0urn:x\0", &free, &bx(b"btrt", &[ &[0; 12] ]) ]);
+    let stsd = full(b"stsd", 0, 0, &[ &1u32.to_be_bytes(), &metx ]);
+    let boxes = roundtrip(&track(b"meta", &stsd));
+    match stsd_entry(&boxes) {
+        BoxNode::XMLMetaDataSampleEntry(e) => {
+            assert_eq!((e.content_encoding.as_str(), e.namespace.as_str()), ("", "urn:x"));
+            assert_eq!(e.other_boxes.len(), 1);
+            assert!(e.bit_rate_box.is_some());
+        },
+        other => panic!("metx: {:?}", other),
+    }
+}